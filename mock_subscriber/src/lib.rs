@@ -0,0 +1,22 @@
+//! Minimal subscriber canister for the integration tests of `set_subscriber`.
+//!
+//! It records the IDs of all todos the backend notifies it of, so the tests can check
+//! which notifications arrived.
+use ic_cdk::{query, update};
+use std::cell::RefCell;
+
+thread_local! {
+    static RECEIVED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Called by the backend canister for every new todo.
+#[update(name = "on_todo_added")]
+fn on_todo_added(id: u64, _title: String) {
+    RECEIVED.with(|received| received.borrow_mut().push(id));
+}
+
+/// Returns the IDs of all todos notified so far, in the order they arrived.
+#[query(name = "received")]
+fn received() -> Vec<u64> {
+    RECEIVED.with(|received| received.borrow().clone())
+}
@@ -1,176 +1,4863 @@
-use ic_cdk::{query, update};
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_cdk::{init, post_upgrade, query, update};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _, StableBTreeMap, StableCell, Storable};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Deref;
+use std::time::Duration;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Page size used by `read_all` when the caller passes `0` and no other default was configured.
+const DEFAULT_PAGE_SIZE: u16 = 10;
+/// Largest page size `read_all` hands out, bigger requests are clamped to it.
+const MAX_PAGE_SIZE: u16 = 100;
+/// Maximum number of characters in the title of a todo.
+const MAX_TODO_LEN: usize = 1000;
+/// Maximum number of characters in the description of a todo.
+const MAX_DESCRIPTION_LEN: usize = 10_000;
+/// Maximum size of an attachment in bytes, 1 MiB keeps uploads below the message size limit.
+const MAX_ATTACHMENT_BYTES: usize = 1024 * 1024;
+/// Number of deleted todos kept per principal for `undo_delete`, the oldest ones are evicted
+/// beyond that.
+const MAX_TRASH_LEN: u64 = 100;
+/// Number of entries kept in the event log, the oldest ones are evicted beyond that.
+const MAX_EVENTS: u64 = 1000;
+/// How long done todos are kept before the daily sweep removes them, unless configured otherwise.
+const DEFAULT_DONE_TTL_NANOS: u64 = 30 * DAY_NANOS;
+/// Size of a Wasm memory page in bytes, heap and stable memory grow in these steps.
+#[cfg(target_arch = "wasm32")]
+const WASM_PAGE_SIZE: u64 = 65536;
+/// Number of todos the canister stores at most, unless configured otherwise.
+const DEFAULT_MAX_TODOS: u64 = 10_000;
+/// One day in nanoseconds, the interval between two sweeps of done todos.
+const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Number of todos a principal may create per minute, unless configured otherwise.
+const DEFAULT_MAX_ADDS_PER_MINUTE: u32 = 100;
+/// One minute in nanoseconds, the length of a rate limit window.
+const MINUTE_NANOS: u64 = 60 * 1_000_000_000;
+
+const TODOS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const GEN_ID_MEMORY_ID: MemoryId = MemoryId::new(1);
+const OWNERS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const TRASH_MEMORY_ID: MemoryId = MemoryId::new(3);
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(4);
+const EVENTS_MEMORY_ID: MemoryId = MemoryId::new(5);
+const FREE_IDS_MEMORY_ID: MemoryId = MemoryId::new(6);
+const SHARED_MEMORY_ID: MemoryId = MemoryId::new(7);
+const ATTACHMENTS_MEMORY_ID: MemoryId = MemoryId::new(8);
+
+/// How important a todo is. The ordering goes from `Low` to `High`.
+#[derive(
+    CandidType, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+/// The order in which `read_all` returns todos.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    IdAsc,
+    IdDesc,
+    CreatedAsc,
+    CreatedDesc,
+    /// Alphabetical by title, ignoring case.
+    TitleAsc,
+}
+
+/// A single todo as stored in the canister and returned to clients.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Todo {
+    pub id: u64,
+    /// The principal that created the todo, only it can change the todo.
+    pub owner: Principal,
+    /// Other principals that may read the todo, see `share`.
+    pub shared_with: Vec<Principal>,
+    /// Short summary shown in lists, never empty.
+    pub title: String,
+    /// Longer free-form notes, may be empty.
+    pub description: String,
+    pub done: bool,
+    /// When the todo was last marked as done, in nanoseconds since the UNIX epoch. `None`
+    /// while it is not done.
+    pub completed_at: Option<u64>,
+    pub priority: Priority,
+    /// Deadline in nanoseconds since the UNIX epoch, if the todo has one.
+    pub due_at: Option<u64>,
+    /// Interval in nanoseconds after which the todo comes back once it is done, see `set_recurrence`.
+    pub recurrence: Option<u64>,
+    /// Lowercase labels without duplicates, in the order they were added.
+    pub tags: Vec<String>,
+    /// Sort key of the todo within its owner's list, see `reorder`. Ties are broken by ID.
+    pub order: u32,
+    /// Pinned todos come first in `read_all`.
+    pub pinned: bool,
+    /// Starred todos are listed by `favorites`.
+    pub starred: bool,
+    /// Label color for the UI as a `#RRGGBB` hex string, see `set_color`.
+    pub color: Option<String>,
+    /// The todo this one is a subtask of, see `add_subtask`.
+    pub parent_id: Option<u64>,
+    /// Creation time in nanoseconds since the UNIX epoch.
+    pub created_at: u64,
+    /// Time of the last change in nanoseconds since the UNIX epoch.
+    pub updated_at: u64,
+    /// Starts at 0 and grows by one with every change, see `update`.
+    pub version: u64,
+}
+
+/// A todo as stored by earlier versions of the canister, which lacked some of the fields.
+///
+/// Candid decodes a missing `opt` field as `None`, so every layout todos were ever stored
+/// in decodes into this, and `From` fills in what is missing. Only used to read stable memory.
+#[derive(CandidType, Deserialize)]
+struct LegacyTodo {
+    id: u64,
+    owner: Option<Principal>,
+    shared_with: Option<Vec<Principal>>,
+    /// Held the text before it was split into `title` and `description`.
+    content: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    done: Option<bool>,
+    completed_at: Option<u64>,
+    priority: Option<Priority>,
+    due_at: Option<u64>,
+    recurrence: Option<u64>,
+    tags: Option<Vec<String>>,
+    order: Option<u32>,
+    pinned: Option<bool>,
+    starred: Option<bool>,
+    color: Option<String>,
+    parent_id: Option<u64>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+    version: Option<u64>,
+}
+
+impl From<LegacyTodo> for Todo {
+    fn from(legacy: LegacyTodo) -> Self {
+        let done = legacy.done.unwrap_or(false);
+        let created_at = legacy.created_at.unwrap_or(0);
+        let updated_at = legacy.updated_at.unwrap_or(created_at);
+        Todo {
+            id: legacy.id,
+            // todos were shared by everyone before they had an owner
+            owner: legacy.owner.unwrap_or_else(Principal::anonymous),
+            shared_with: legacy.shared_with.unwrap_or_default(),
+            title: legacy.title.or(legacy.content).unwrap_or_default(),
+            description: legacy.description.unwrap_or_default(),
+            done,
+            // todos done before this was tracked count as completed at their last change
+            completed_at: legacy.completed_at.or_else(|| done.then_some(updated_at)),
+            priority: legacy.priority.unwrap_or_default(),
+            due_at: legacy.due_at,
+            recurrence: legacy.recurrence,
+            tags: legacy.tags.unwrap_or_default(),
+            // the same order new todos get, see `new_todo`
+            order: legacy
+                .order
+                .unwrap_or_else(|| u32::try_from(legacy.id).unwrap_or(u32::MAX)),
+            pinned: legacy.pinned.unwrap_or(false),
+            starred: legacy.starred.unwrap_or(false),
+            color: legacy.color,
+            parent_id: legacy.parent_id,
+            created_at,
+            updated_at,
+            version: legacy.version.unwrap_or(0),
+        }
+    }
+}
+
+/// A deleted todo waiting in the trash for `undo_delete`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TrashedTodo {
+    todo: Todo,
+    /// Time of the deletion in nanoseconds since the UNIX epoch.
+    deleted_at: u64,
+}
+
+/// A `TrashedTodo` as stored by earlier versions, see `LegacyTodo`.
+#[derive(CandidType, Deserialize)]
+struct LegacyTrashedTodo {
+    todo: LegacyTodo,
+    deleted_at: u64,
+}
+
+impl From<LegacyTrashedTodo> for TrashedTodo {
+    fn from(legacy: LegacyTrashedTodo) -> Self {
+        TrashedTodo {
+            todo: legacy.todo.into(),
+            deleted_at: legacy.deleted_at,
+        }
+    }
+}
+
+/// The fields `patch` changes, `None` leaves a field as it is.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct TodoPatch {
+    pub title: Option<String>,
+    pub done: Option<bool>,
+    pub priority: Option<Priority>,
+    /// `Some(None)` removes the due date, while `None` keeps it.
+    pub due_at: Option<Option<u64>>,
+}
+
+/// The settings `update_config` changes, `None` leaves a setting as it is.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct ConfigPatch {
+    /// Between `1` and `MAX_PAGE_SIZE`, like in `InitArgs`.
+    pub default_page_size: Option<u16>,
+    pub max_todos: Option<u64>,
+    pub dedup: Option<bool>,
+    pub done_ttl_nanos: Option<u64>,
+}
+
+/// Everything `create` needs to set up a todo in one call.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CreateTodo {
+    pub title: String,
+    pub description: String,
+    /// Normalized like in `add_tag`, duplicates are dropped.
+    pub tags: Vec<String>,
+    pub priority: Priority,
+    /// Deadline in nanoseconds since the UNIX epoch, if the todo has one.
+    pub due_at: Option<u64>,
+}
+
+/// Implements `Storable` for types that are stored in stable memory as candid.
+///
+/// `T => Legacy` makes values that don't decode as `T` fall back to decoding as `Legacy`
+/// and converting with `From`, so values stored by earlier versions stay readable.
+macro_rules! impl_candid_storable {
+    ($($t:ty $(=> $legacy:ty)?),*) => {
+        $(
+            impl Storable for $t {
+                fn to_bytes(&self) -> Cow<'_, [u8]> {
+                    Cow::Owned(
+                        Encode!(self).expect(concat!("Failed to encode ", stringify!($t))),
+                    )
+                }
+
+                fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                    let decoded = Decode!(bytes.as_ref(), Self);
+                    $(
+                        let decoded = decoded
+                            .or_else(|_| Decode!(bytes.as_ref(), $legacy).map(Self::from));
+                    )?
+                    decoded.expect(concat!("Failed to decode ", stringify!($t)))
+                }
+
+                const BOUND: Bound = Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+/// The arguments passed when installing the canister.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct InitArgs {
+    /// Page size `read_all` uses when the caller passes `0`, at most `MAX_PAGE_SIZE`.
+    pub default_page_size: u16,
+    /// The principal allowed to call destructive endpoints like `clear_all`, must not be
+    /// the anonymous principal.
+    pub admin: Principal,
+    /// How long done todos are kept after their last change, defaults to 30 days.
+    pub done_ttl_nanos: Option<u64>,
+    /// How many todos of all principals the canister stores at most, defaults to 10000.
+    pub max_todos: Option<u64>,
+    /// How many todos a principal may create per minute, in any way, defaults to 100.
+    pub max_adds_per_minute: Option<u32>,
+}
+
+/// Canister settings, stored in stable memory so they survive upgrades.
+///
+/// Starts out from the `InitArgs`, with defaults for everything they leave open.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Config {
+    pub default_page_size: u16,
+    /// `None` for canisters installed before the admin existed, see `set_admin`.
+    pub admin: Option<Principal>,
+    pub done_ttl_nanos: u64,
+    /// Whether `add` rejects a title the caller already has a todo with, see `set_dedup`.
+    pub dedup: bool,
+    pub max_todos: u64,
+    /// Whether IDs of deleted todos are handed out again, see `set_reuse_ids`.
+    pub reuse_ids: bool,
+    pub max_adds_per_minute: u32,
+    /// Canister notified of every new todo, see `set_subscriber`.
+    pub subscriber: Option<Principal>,
+    /// Whether runs of whitespace in titles are collapsed, see `set_collapse_whitespace`.
+    pub collapse_whitespace: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            default_page_size: DEFAULT_PAGE_SIZE,
+            admin: None,
+            done_ttl_nanos: DEFAULT_DONE_TTL_NANOS,
+            dedup: false,
+            max_todos: DEFAULT_MAX_TODOS,
+            reuse_ids: false,
+            max_adds_per_minute: DEFAULT_MAX_ADDS_PER_MINUTE,
+            subscriber: None,
+            collapse_whitespace: false,
+        }
+    }
+}
+
+/// A `Config` as stored by earlier versions, see `LegacyTodo`. Missing settings get their defaults.
+#[derive(CandidType, Deserialize)]
+struct LegacyConfig {
+    default_page_size: Option<u16>,
+    admin: Option<Principal>,
+    done_ttl_nanos: Option<u64>,
+    dedup: Option<bool>,
+    max_todos: Option<u64>,
+    reuse_ids: Option<bool>,
+    max_adds_per_minute: Option<u32>,
+    subscriber: Option<Principal>,
+    collapse_whitespace: Option<bool>,
+}
+
+impl From<LegacyConfig> for Config {
+    fn from(legacy: LegacyConfig) -> Self {
+        let defaults = Config::default();
+        Config {
+            default_page_size: legacy
+                .default_page_size
+                .unwrap_or(defaults.default_page_size),
+            admin: legacy.admin,
+            done_ttl_nanos: legacy.done_ttl_nanos.unwrap_or(defaults.done_ttl_nanos),
+            dedup: legacy.dedup.unwrap_or(defaults.dedup),
+            max_todos: legacy.max_todos.unwrap_or(defaults.max_todos),
+            reuse_ids: legacy.reuse_ids.unwrap_or(defaults.reuse_ids),
+            max_adds_per_minute: legacy
+                .max_adds_per_minute
+                .unwrap_or(defaults.max_adds_per_minute),
+            subscriber: legacy.subscriber,
+            collapse_whitespace: legacy
+                .collapse_whitespace
+                .unwrap_or(defaults.collapse_whitespace),
+        }
+    }
+}
+
+/// An entry of the event log, `at` is the IC time of the change in nanoseconds.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A todo was created or restored from the trash.
+    Added { id: u64, at: u64 },
+    /// A todo was changed, anything that bumps its `version` counts.
+    Updated { id: u64, at: u64 },
+    /// A todo was deleted, through `delete`, as a subtask or by the sweep of done todos.
+    Deleted { id: u64, at: u64 },
+    /// The due date of a todo that is not done yet has passed, see `set_reminder`.
+    Reminder { id: u64, at: u64 },
+}
+
+impl_candid_storable!(
+    Todo => LegacyTodo,
+    TrashedTodo => LegacyTrashedTodo,
+    Config => LegacyConfig,
+    Event
+);
+
+/// The errors returned by the canister methods, machine readable over candid.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum TodoError {
+    /// There is no todo with this ID, or it belongs to another principal.
+    NotFound(u64),
+    /// The title is empty or only consists of whitespace.
+    EmptyContent,
+    /// The title or description is longer than the contained maximum number of characters.
+    TooLong(usize),
+    /// The requested page holds no todos.
+    InvalidPage(u16),
+    /// The entry at `index` of a batch is invalid, nothing of the batch was applied.
+    InvalidEntry { index: u64, error: Box<TodoError> },
+    /// The caller is not allowed to call this endpoint.
+    Unauthorized,
+    /// The todo was changed since the client read it, `current` is its version now.
+    VersionConflict { current: u64 },
+    /// Dedup mode is on and the caller already has a todo with this title.
+    Duplicate,
+    /// Storing more todos would exceed the configured maximum, which is contained.
+    LimitReached(u64),
+    /// The input is not valid JSON or doesn't have the expected shape, with the parser's message.
+    InvalidJson(String),
+    /// The input breaks a rule of the endpoint, the contained message says which.
+    InvalidInput(String),
+    /// The color is not a `#RRGGBB` hex string.
+    InvalidColor,
+    /// The caller created too many todos within the last minute, see `InitArgs::max_adds_per_minute`.
+    RateLimited,
+    /// The attachment is larger than the contained maximum number of bytes.
+    TooLarge(usize),
+    /// The contained ID is already used by another todo.
+    IdTaken(u64),
+    /// The page size is `0` or larger than the contained maximum.
+    InvalidPageSize(u16),
+}
+
+/// One page of todos as returned by `read_all`.
+#[derive(CandidType, Deserialize, Debug)]
+pub struct Page {
+    pub items: Vec<Todo>,
+    /// The next page number, `None` on the last page.
+    pub next_page: Option<u16>,
+    pub total_items: u64,
+    pub total_pages: u16,
+}
+
+/// One page of `(id, title)` pairs and the next page number, see `read_all_with_ids`.
+type TitlePage = (Vec<(u64, String)>, Option<u16>);
+
+/// An HTTP request forwarded by the HTTP gateway, see `http_request`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    /// The path and query string of the request, e.g. `/metrics?format=text`.
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// The response handed back to the HTTP gateway.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Memory usage of the canister as returned by `memory_stats`.
+#[derive(CandidType, Deserialize, Debug)]
+pub struct MemStats {
+    /// Size of the Wasm heap in bytes.
+    pub heap_bytes: u64,
+    /// Size of the stable memory in bytes.
+    pub stable_bytes: u64,
+    /// Number of stored todos of all principals.
+    pub todo_count: u64,
+}
+
+/// Aggregate numbers about the caller's todos as returned by `stats`.
+#[derive(CandidType, Deserialize, Debug, Default)]
+pub struct Stats {
+    pub total: u64,
+    pub done: u64,
+    pub pending: u64,
+    /// Pending todos whose due date has passed, these also count as `pending`.
+    pub overdue: u64,
+}
+
+/// Length numbers about the title and description of a todo as returned by `content_stats`.
+#[derive(CandidType, Deserialize, Debug, Default, PartialEq)]
+pub struct ContentStats {
+    /// Number of Unicode scalar values, not bytes.
+    pub chars: u64,
+    /// Number of whitespace separated words.
+    pub words: u64,
+    pub lines: u64,
+}
+
+/// The stable map holding all todos, which keeps the certified hash of its contents up to date.
+///
+/// The hash is the sum modulo 2^256 of the SHA-256 hashes of all stored todos, so every
+/// change updates it in constant time. Reads go through `Deref`, changes have to use the
+/// methods below so neither the hash nor the indexes can get out of sync. Removing a todo
+/// also drops its attachment, see `set_attachment`, and the timers for reminders follow the
+/// due dates of the stored todos, see `set_reminder`.
+///
+/// Two indexes keyed by `(principal, id)` make the todos a principal owns, and those shared
+/// with it, a range, so reads scoped to the caller never visit the todos of others.
+struct TodoDB {
+    map: StableBTreeMap<u64, Todo, Memory>,
+    owners: StableBTreeMap<(Principal, u64), (), Memory>,
+    shared: StableBTreeMap<(Principal, u64), (), Memory>,
+    hash: [u8; 32],
+}
+
+impl TodoDB {
+    /// Loads the map and its indexes from stable memory and computes the hash of what is
+    /// already stored.
+    ///
+    /// Todos stored before there was an index of owners are indexed here once.
+    fn init() -> Self {
+        let map: StableBTreeMap<u64, Todo, Memory> =
+            StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TODOS_MEMORY_ID)));
+        let mut owners =
+            StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(OWNERS_MEMORY_ID)));
+        let shared =
+            StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SHARED_MEMORY_ID)));
+        let reindex = owners.is_empty();
+        let mut hash = [0; 32];
+        for (id, todo) in map.iter() {
+            add_hash(&mut hash, &todo_hash(&todo));
+            if reindex {
+                owners.insert((todo.owner, id), ());
+            }
+        }
+        TodoDB {
+            map,
+            owners,
+            shared,
+            hash,
+        }
+    }
+
+    fn insert(&mut self, id: u64, todo: Todo) -> Option<Todo> {
+        add_hash(&mut self.hash, &todo_hash(&todo));
+        let remind_at = reminder_due(&todo);
+        let (owner, shared_with) = (todo.owner, todo.shared_with.clone());
+        let old = self.map.insert(id, todo);
+        if let Some(old) = &old {
+            sub_hash(&mut self.hash, &todo_hash(old));
+            self.unindex(id, old);
+        }
+        self.owners.insert((owner, id), ());
+        for principal in shared_with {
+            self.shared.insert((principal, id), ());
+        }
+        if old.as_ref().map(reminder_due) != Some(remind_at) {
+            set_reminder(id, remind_at);
+        }
+        self.certify();
+        old
+    }
+
+    fn remove(&mut self, id: &u64) -> Option<Todo> {
+        let old = self.map.remove(id);
+        if let Some(old) = &old {
+            sub_hash(&mut self.hash, &todo_hash(old));
+            self.unindex(*id, old);
+            self.certify();
+            ATTACHMENTS.with(|attachments| attachments.borrow_mut().remove(id));
+            set_reminder(*id, None);
+        }
+        old
+    }
+
+    /// Removes all todos.
+    fn clear(&mut self) {
+        self.map = StableBTreeMap::new(MEMORY_MANAGER.with(|m| m.borrow().get(TODOS_MEMORY_ID)));
+        self.owners =
+            StableBTreeMap::new(MEMORY_MANAGER.with(|m| m.borrow().get(OWNERS_MEMORY_ID)));
+        self.shared =
+            StableBTreeMap::new(MEMORY_MANAGER.with(|m| m.borrow().get(SHARED_MEMORY_ID)));
+        self.hash = [0; 32];
+        self.certify();
+        ATTACHMENTS.with(|attachments| {
+            *attachments.borrow_mut() =
+                StableBTreeMap::new(MEMORY_MANAGER.with(|m| m.borrow().get(ATTACHMENTS_MEMORY_ID)));
+        });
+        for (_, timer) in REMINDERS.with(|reminders| reminders.take()) {
+            clear_timer(timer);
+        }
+    }
+
+    /// Drops the index entries of `todo`, stored under `id`.
+    fn unindex(&mut self, id: u64, todo: &Todo) {
+        self.owners.remove(&(todo.owner, id));
+        for principal in &todo.shared_with {
+            self.shared.remove(&(*principal, id));
+        }
+    }
+
+    /// The IDs of the todos `owner` owns, in increasing order and starting at `from`.
+    fn owned_ids(&self, owner: Principal, from: u64) -> impl Iterator<Item = u64> + '_ {
+        self.owners
+            .range((owner, from)..=(owner, u64::MAX))
+            .map(|((_, id), _)| id)
+    }
+
+    /// The IDs of the todos shared with `reader`, in increasing order and starting at `from`.
+    fn shared_ids(&self, reader: Principal, from: u64) -> impl Iterator<Item = u64> + '_ {
+        self.shared
+            .range((reader, from)..=(reader, u64::MAX))
+            .map(|((_, id), _)| id)
+    }
+
+    /// Loads the todos with the given IDs, which have to come from one of the indexes.
+    fn load(&self, ids: impl IntoIterator<Item = u64>) -> Vec<Todo> {
+        ids.into_iter()
+            .map(|id| self.map.get(&id).expect("Indexed todos are stored"))
+            .collect()
+    }
+
+    /// Publishes the current hash as the certified data of the canister.
+    fn certify(&self) {
+        set_certified_data(&self.hash);
+    }
+}
+
+impl Deref for TodoDB {
+    type Target = StableBTreeMap<u64, Todo, Memory>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+fn todo_hash(todo: &Todo) -> [u8; 32] {
+    Sha256::digest(todo.to_bytes()).into()
+}
+
+/// Adds `hash` to `acc`, both read as big-endian numbers, modulo 2^256.
+fn add_hash(acc: &mut [u8; 32], hash: &[u8; 32]) {
+    let mut carry = 0;
+    for (a, h) in acc.iter_mut().zip(hash).rev() {
+        let sum = *a as u16 + *h as u16 + carry;
+        *a = sum as u8;
+        carry = sum >> 8;
+    }
+}
+
+/// Subtracts `hash` from `acc`, both read as big-endian numbers, modulo 2^256.
+fn sub_hash(acc: &mut [u8; 32], hash: &[u8; 32]) {
+    let mut borrow = 0;
+    for (a, h) in acc.iter_mut().zip(hash).rev() {
+        let diff = *a as i16 - *h as i16 - borrow;
+        borrow = i16::from(diff < 0);
+        *a = diff.rem_euclid(256) as u8;
+    }
+}
 
-type TodoDB = BTreeMap<u16, String>;
 thread_local! {
-    static TODOMAP: RefCell<TodoDB> = RefCell::default();
-    static GEN_ID: RefCell<u16> = RefCell::new(0);
+    // The memory manager splits stable memory into virtual memories, one per structure.
+    // Everything stored through it survives upgrades without explicit serialization.
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static TODOMAP: RefCell<TodoDB> = RefCell::new(TodoDB::init());
+
+    static GEN_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(GEN_ID_MEMORY_ID)), 0)
+            .expect("Failed to initialize the todo ID counter")
+    );
+
+    // Recently deleted todos keyed by their ID, bounded by `MAX_TRASH_LEN` per owner.
+    static TRASH: RefCell<StableBTreeMap<u64, TrashedTodo, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TRASH_MEMORY_ID)))
+    );
+
+    static CONFIG: RefCell<StableCell<Config, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY_ID)),
+            Config::default(),
+        )
+        .expect("Failed to initialize the config")
+    );
+
+    // The latest mutations keyed by a sequence number, bounded by `MAX_EVENTS`.
+    static EVENTS: RefCell<StableBTreeMap<u64, Event, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(EVENTS_MEMORY_ID)))
+    );
+
+    // IDs of deleted todos that `add` may hand out again, used as a set. Only filled while
+    // `Config::reuse_ids` is on, and never contains the ID of a stored todo.
+    static FREE_IDS: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(FREE_IDS_MEMORY_ID)))
+    );
+
+    // Attachments keyed by the ID of their todo. They are kept apart from the todos, so
+    // listing todos never has to load them.
+    static ATTACHMENTS: RefCell<StableBTreeMap<u64, Vec<u8>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ATTACHMENTS_MEMORY_ID)))
+    );
+
+    // Start of the current rate limit window and the number of adds in it, per principal.
+    // Windows don't need to survive upgrades, so they live on the heap.
+    static ADD_WINDOWS: RefCell<HashMap<Principal, (u64, u32)>> = RefCell::new(HashMap::new());
+
+    // The pending reminder timer per todo, see `set_reminder`. Timers don't survive
+    // upgrades, `post_upgrade` starts them again.
+    static REMINDERS: RefCell<HashMap<u64, TimerId>> = RefCell::new(HashMap::new());
+}
+
+/// Stores the configuration passed at install time.
+///
+/// Traps if `default_page_size` is `0` or larger than `MAX_PAGE_SIZE`, or if `admin` is the
+/// anonymous principal, so a broken configuration never gets installed.
+#[init]
+fn init(arg: InitArgs) {
+    if arg.default_page_size == 0 || arg.default_page_size > MAX_PAGE_SIZE {
+        ic_cdk::trap(&format!(
+            "default_page_size must be between 1 and {}",
+            MAX_PAGE_SIZE
+        ));
+    }
+    if arg.admin == Principal::anonymous() {
+        ic_cdk::trap("The admin must not be the anonymous principal");
+    }
+    save_config(Config {
+        default_page_size: arg.default_page_size,
+        admin: Some(arg.admin),
+        done_ttl_nanos: arg.done_ttl_nanos.unwrap_or(DEFAULT_DONE_TTL_NANOS),
+        dedup: false,
+        reuse_ids: false,
+        max_todos: arg.max_todos.unwrap_or(DEFAULT_MAX_TODOS),
+        max_adds_per_minute: arg
+            .max_adds_per_minute
+            .unwrap_or(DEFAULT_MAX_ADDS_PER_MINUTE),
+        subscriber: None,
+        collapse_whitespace: false,
+    });
+    TODOMAP.with(|todomap| todomap.borrow().certify());
+    start_daily_cleanup();
+}
+
+/// When a reminder for `todo` is due: at its due date, as long as it is not done.
+fn reminder_due(todo: &Todo) -> Option<u64> {
+    todo.due_at.filter(|_| !todo.done)
+}
+
+/// Replaces the reminder timer of the todo `id` by one firing at `remind_at`.
+///
+/// Passing `None` just cancels the current timer. Due dates that have already passed get
+/// no reminder, `overdue` lists those. When the timer fires, an `Event::Reminder` is
+/// appended to the event log.
+fn set_reminder(id: u64, remind_at: Option<u64>) {
+    REMINDERS.with(|reminders| {
+        let mut reminders = reminders.borrow_mut();
+        if let Some(timer) = reminders.remove(&id) {
+            clear_timer(timer);
+        }
+        let delay = remind_at.and_then(|remind_at| remind_at.checked_sub(now()));
+        if let Some(delay) = delay.filter(|&delay| delay > 0) {
+            let timer = set_timer(Duration::from_nanos(delay), move || {
+                REMINDERS.with(|reminders| reminders.borrow_mut().remove(&id));
+                record_event(Event::Reminder { id, at: now() });
+            });
+            reminders.insert(id, timer);
+        }
+    });
+}
+
+/// Runs `f` once after `delay`.
+#[cfg(target_arch = "wasm32")]
+fn set_timer(delay: Duration, f: impl FnOnce() + 'static) -> TimerId {
+    ic_cdk_timers::set_timer(delay, f)
+}
+
+/// There are no timers outside of a canister, native unit tests never fire them.
+#[cfg(not(target_arch = "wasm32"))]
+fn set_timer(_delay: Duration, _f: impl FnOnce() + 'static) -> TimerId {
+    TimerId::default()
+}
+
+/// Runs `f` every `interval`, starting one `interval` from now.
+#[cfg(target_arch = "wasm32")]
+fn set_timer_interval(interval: Duration, f: impl FnMut() + 'static) -> TimerId {
+    ic_cdk_timers::set_timer_interval(interval, f)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn set_timer_interval(_interval: Duration, _f: impl FnMut() + 'static) -> TimerId {
+    TimerId::default()
+}
+
+/// Cancels a timer started with `set_timer` or `set_timer_interval`.
+#[cfg(target_arch = "wasm32")]
+fn clear_timer(timer: TimerId) {
+    ic_cdk_timers::clear_timer(timer);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clear_timer(_timer: TimerId) {}
+
+/// Returns a copy of the current configuration.
+fn config() -> Config {
+    CONFIG.with(|c| c.borrow().get().clone())
+}
+
+/// Replaces the stored configuration.
+fn save_config(config: Config) {
+    CONFIG.with(|c| {
+        c.borrow_mut()
+            .set(config)
+            .expect("Failed to persist the config")
+    });
+}
+
+/// Makes sure the caller is the admin, the anonymous principal never is.
+fn require_admin() -> Result<(), TodoError> {
+    let caller = caller();
+    if caller == Principal::anonymous() || config().admin != Some(caller) {
+        return Err(TodoError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// Returns the current canister settings.
+///
+/// # Returns
+///
+/// The `Config` as set by `init` and changed by the admin endpoints since.
+#[query(name = "get_config")]
+fn get_config() -> Config {
+    config()
+}
+
+/// Sets the admin principal of a canister that doesn't have one yet.
+///
+/// Canisters installed before `InitArgs` existed have no admin, so nobody can call the
+/// destructive endpoints. This update function lets a controller fix that exactly once.
+///
+/// # Parameters
+///
+/// * `admin` (Principal): The new admin, must not be the anonymous principal.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized` in the following cases:
+/// * The canister already has an admin.
+/// * The caller is not a controller of the canister, or is anonymous.
+/// * `admin` is the anonymous principal.
+#[update(name = "set_admin")]
+fn set_admin(admin: Principal) -> Result<(), TodoError> {
+    let caller = caller();
+    if caller == Principal::anonymous() || !ic_cdk::api::is_controller(&caller) {
+        return Err(TodoError::Unauthorized);
+    }
+    let mut config = config();
+    if config.admin.is_some() || admin == Principal::anonymous() {
+        return Err(TodoError::Unauthorized);
+    }
+    config.admin = Some(admin);
+    save_config(config);
+    Ok(())
+}
+
+/// Changes how long done todos are kept before the daily sweep removes them.
+///
+/// # Parameters
+///
+/// * `done_ttl_nanos` (u64): The time in nanoseconds a todo has to be done, without any
+///                           other change, before it is removed.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[update(name = "set_done_ttl")]
+fn set_done_ttl(done_ttl_nanos: u64) -> Result<(), TodoError> {
+    require_admin()?;
+    let mut config = config();
+    config.done_ttl_nanos = done_ttl_nanos;
+    save_config(config);
+    Ok(())
+}
+
+/// Turns the dedup mode on or off.
+///
+/// While it is on, `add` and `add_many` reject titles the caller already has a todo with.
+/// Titles are compared after trimming, but otherwise exactly. Existing duplicates are kept.
+///
+/// # Parameters
+///
+/// * `enabled` (bool): Whether duplicate titles are rejected.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[update(name = "set_dedup")]
+fn set_dedup(enabled: bool) -> Result<(), TodoError> {
+    require_admin()?;
+    let mut config = config();
+    config.dedup = enabled;
+    save_config(config);
+    Ok(())
+}
+
+/// Turns the collapsing of whitespace in titles on or off.
+///
+/// It is off by default. While it is on, every run of spaces, tabs and other whitespace
+/// in a new or changed title becomes a single space, so `a   b\tc` is stored as `a b c`.
+/// Line breaks are kept. Titles that are already stored don't change.
+///
+/// # Parameters
+///
+/// * `enabled` (bool): Whether whitespace is collapsed.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[update(name = "set_collapse_whitespace")]
+fn set_collapse_whitespace(enabled: bool) -> Result<(), TodoError> {
+    require_admin()?;
+    let mut config = config();
+    config.collapse_whitespace = enabled;
+    save_config(config);
+    Ok(())
+}
+
+/// Turns the reuse of deleted IDs on or off.
+///
+/// While it is on, the IDs of deleted todos are remembered and new todos take the smallest
+/// of them before a fresh ID is generated. Clients must then not assume that an ID always
+/// refers to the same todo. A reused ID can no longer be restored with `undo_delete`.
+/// Only IDs deleted while reuse is on are handed out again.
+///
+/// # Parameters
+///
+/// * `enabled` (bool): Whether deleted IDs are reused.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[update(name = "set_reuse_ids")]
+fn set_reuse_ids(enabled: bool) -> Result<(), TodoError> {
+    require_admin()?;
+    let mut config = config();
+    config.reuse_ids = enabled;
+    save_config(config);
+    Ok(())
+}
+
+/// Sets or removes the canister that is notified of new todos.
+///
+/// Whenever a todo is created, by `add`, `create`, `add_many`, `duplicate`, `add_subtask`,
+/// a recurring `mark_done` or one of the imports, the subscriber gets a one-way call of its
+/// `on_todo_added` method with the ID and title of the new todo, `(nat64, text)` in candid,
+/// right after the todo is stored. Notifications are best effort: if one can't be sent or
+/// the subscriber rejects it, the todo is created anyway and nothing is retried.
+///
+/// # Parameters
+///
+/// * `subscriber` (Option<Principal>): The canister to notify, or `None` to stop notifying.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[update(name = "set_subscriber")]
+fn set_subscriber(subscriber: Option<Principal>) -> Result<(), TodoError> {
+    require_admin()?;
+    let mut config = config();
+    config.subscriber = subscriber;
+    save_config(config);
+    Ok(())
+}
+
+/// Changes several canister settings at once.
+///
+/// Only the fields of `patch` that are `Some` are applied, and nothing is applied if one
+/// of them is invalid. Lowering `max_todos` below the number of stored todos keeps them,
+/// but no new todos can be added until enough are deleted.
+///
+/// # Parameters
+///
+/// * `patch` (ConfigPatch): The settings to change.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `Unauthorized`: If the caller is not the admin principal.
+/// * `InvalidPageSize(MAX_PAGE_SIZE)`: If `default_page_size` is `0` or larger than
+///   `MAX_PAGE_SIZE` (100).
+#[update(name = "update_config")]
+fn update_config(patch: ConfigPatch) -> Result<(), TodoError> {
+    require_admin()?;
+    let mut config = config();
+    if let Some(default_page_size) = patch.default_page_size {
+        if default_page_size == 0 || default_page_size > MAX_PAGE_SIZE {
+            return Err(TodoError::InvalidPageSize(MAX_PAGE_SIZE));
+        }
+        config.default_page_size = default_page_size;
+    }
+    if let Some(max_todos) = patch.max_todos {
+        config.max_todos = max_todos;
+    }
+    if let Some(dedup) = patch.dedup {
+        config.dedup = dedup;
+    }
+    if let Some(done_ttl_nanos) = patch.done_ttl_nanos {
+        config.done_ttl_nanos = done_ttl_nanos;
+    }
+    save_config(config);
+    Ok(())
+}
+
+/// Tells the subscriber about a new todo, if there is one. Failures are ignored.
+fn notify_subscriber(id: u64, title: &str) {
+    if let Some(subscriber) = config().subscriber {
+        let _ = notify(subscriber, "on_todo_added", (id, title));
+    }
+}
+
+/// Remembers the ID of a deleted todo for reuse, if reuse is on.
+fn free_id(id: u64) {
+    if config().reuse_ids {
+        FREE_IDS.with(|free_ids| free_ids.borrow_mut().insert(id, ()));
+    }
+}
+
+/// Makes sure `additional` more todos fit below the configured `max_todos`.
+///
+/// The limit applies to the todos currently stored, deleted todos don't count.
+fn ensure_capacity(additional: u64) -> Result<(), TodoError> {
+    let max_todos = config().max_todos;
+    let stored = TODOMAP.with(|todomap| todomap.borrow().len());
+    if stored.saturating_add(additional) > max_todos {
+        return Err(TodoError::LimitReached(max_todos));
+    }
+    Ok(())
+}
+
+/// Returns the titles of all todos of `owner` if dedup mode is on, `None` otherwise.
+fn dedup_titles(owner: Principal) -> Option<HashSet<String>> {
+    config().dedup.then(|| {
+        TODOMAP.with(|todomap| {
+            owned_todos(&todomap.borrow(), owner)
+                .into_iter()
+                .map(|todo| todo.title)
+                .collect()
+        })
+    })
+}
+
+/// Removes every done todo whose last change is more than `done_ttl_nanos` before `now`.
+///
+/// Swept todos are deleted like with `delete`: their subtasks go with them and all of them
+/// end up in the trash. Returns how many done todos were removed, not counting subtasks.
+fn sweep_done_todos(now: u64) -> u64 {
+    let ttl = config().done_ttl_nanos;
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let expired: Vec<u64> = todomap
+            .iter()
+            .filter(|(_, todo)| todo.done && now.saturating_sub(todo.updated_at) > ttl)
+            .map(|(id, _)| id)
+            .collect();
+        // an expired subtask may already be gone with its expired parent
+        expired
+            .into_iter()
+            .filter(|&id| remove_with_subtasks(&mut todomap, id))
+            .count() as u64
+    })
+}
+
+/// Counts `adds` new todos of `owner` and fails if they exceed `max_adds_per_minute`.
+///
+/// Every way of creating todos goes through this, batches count each of their todos.
+/// Each principal gets a fixed one minute window starting with its first add, the count
+/// starts over once the window has passed. Nothing is counted if the check fails.
+fn check_rate_limit(owner: Principal, adds: u64) -> Result<(), TodoError> {
+    let limit = config().max_adds_per_minute;
+    let now = now();
+    ADD_WINDOWS.with(|windows| {
+        let mut windows = windows.borrow_mut();
+        let (start, count) = windows.entry(owner).or_insert((now, 0));
+        if now.saturating_sub(*start) >= MINUTE_NANOS {
+            *start = now;
+            *count = 0;
+        }
+        if u64::from(*count).saturating_add(adds) > u64::from(limit) {
+            return Err(TodoError::RateLimited);
+        }
+        // fits into a u32, the new count is at most `limit`
+        *count += adds as u32;
+        Ok(())
+    })
+}
+
+/// Starts the timer that runs `daily_cleanup` once per day of IC time.
+///
+/// Timers don't survive upgrades, so `init` and `post_upgrade` both call this.
+fn start_daily_cleanup() {
+    set_timer_interval(Duration::from_nanos(DAY_NANOS), daily_cleanup);
+}
+
+/// Sweeps old done todos and drops the rate limit windows that have passed.
+fn daily_cleanup() {
+    let now = now();
+    sweep_done_todos(now);
+    ADD_WINDOWS.with(|windows| {
+        windows
+            .borrow_mut()
+            .retain(|_, (start, _)| now.saturating_sub(*start) < MINUTE_NANOS)
+    });
+}
+
+/// Returns the current IC time in nanoseconds since the UNIX epoch.
+#[cfg(target_arch = "wasm32")]
+fn now() -> u64 {
+    ic_cdk::api::time()
+}
+
+/// `ic_cdk::api::time` traps outside of a canister, so native unit tests use the system clock.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System clock is set before the UNIX epoch")
+        .as_nanos() as u64
+}
+
+/// Returns the principal that made the current call.
+#[cfg(target_arch = "wasm32")]
+fn caller() -> Principal {
+    ic_cdk::caller()
+}
+
+/// `ic_cdk::caller` traps outside of a canister, so native unit tests act as the anonymous
+/// principal.
+#[cfg(not(target_arch = "wasm32"))]
+fn caller() -> Principal {
+    Principal::anonymous()
+}
+
+/// Sets the certified data of the canister, see `TodoDB`.
+#[cfg(target_arch = "wasm32")]
+fn set_certified_data(data: &[u8]) {
+    ic_cdk::api::set_certified_data(data);
+}
+
+/// There is no certified data outside of a canister, so native unit tests skip it.
+#[cfg(not(target_arch = "wasm32"))]
+fn set_certified_data(_data: &[u8]) {}
+
+/// Sends a one-way call, see `ic_cdk::notify`.
+#[cfg(target_arch = "wasm32")]
+fn notify<T: candid::utils::ArgumentEncoder>(
+    id: Principal,
+    method: &str,
+    args: T,
+) -> Result<(), ic_cdk::api::call::RejectionCode> {
+    ic_cdk::notify(id, method, args)
+}
+
+/// There are no other canisters outside of a canister, so native unit tests send nothing.
+#[cfg(not(target_arch = "wasm32"))]
+fn notify<T: candid::utils::ArgumentEncoder>(
+    _id: Principal,
+    _method: &str,
+    _args: T,
+) -> Result<(), ic_cdk::api::call::RejectionCode> {
+    Ok(())
+}
+
+/// Returns the certificate of the certified data, empty outside of query calls.
+#[cfg(target_arch = "wasm32")]
+fn data_certificate() -> Vec<u8> {
+    ic_cdk::api::data_certificate().unwrap_or_default()
+}
+
+/// There are no certificates outside of a canister, so native unit tests get an empty one.
+#[cfg(not(target_arch = "wasm32"))]
+fn data_certificate() -> Vec<u8> {
+    Vec::new()
+}
+
+/// Returns the size of the Wasm heap in bytes.
+#[cfg(target_arch = "wasm32")]
+fn heap_bytes() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+}
+
+/// There is no Wasm heap outside of a canister, so native unit tests report `0`.
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_bytes() -> u64 {
+    0
+}
+
+/// Returns the size of the stable memory in bytes.
+#[cfg(target_arch = "wasm32")]
+fn stable_bytes() -> u64 {
+    ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE
+}
+
+/// There is no stable memory outside of a canister, so native unit tests report `0`.
+#[cfg(not(target_arch = "wasm32"))]
+fn stable_bytes() -> u64 {
+    0
+}
+
+/// Returns the todo with the given ID if it belongs to `owner`.
+fn get_owned(todomap: &TodoDB, id: u64, owner: Principal) -> Option<Todo> {
+    todomap.get(&id).filter(|todo| todo.owner == owner)
+}
+
+/// Applies `f` to one of the caller's todos and stores the result as a change, see `touch`.
+///
+/// Fails with `TodoError::NotFound(id)` if the caller has no todo with this ID. If `f`
+/// fails, its error is returned and the todo is left as it was.
+fn with_todo_mut<R>(
+    id: u64,
+    f: impl FnOnce(&mut Todo) -> Result<R, TodoError>,
+) -> Result<R, TodoError> {
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let mut todo = get_owned(&todomap, id, caller()).ok_or(TodoError::NotFound(id))?;
+        let result = f(&mut todo)?;
+        touch(&mut todo);
+        todomap.insert(id, todo);
+        Ok(result)
+    })
+}
+
+/// Whether `principal` may read `todo`, because it owns the todo or the todo is shared with it.
+fn is_visible_to(todo: &Todo, principal: Principal) -> bool {
+    todo.owner == principal || todo.shared_with.contains(&principal)
+}
+
+/// Returns the todo with the given ID if `reader` may read it, see `is_visible_to`.
+fn get_visible(todomap: &TodoDB, id: u64, reader: Principal) -> Option<Todo> {
+    todomap.get(&id).filter(|todo| is_visible_to(todo, reader))
+}
+
+/// Records a change of `todo` by refreshing `updated_at`, bumping `version` and logging it.
+fn touch(todo: &mut Todo) {
+    todo.updated_at = now();
+    todo.version += 1;
+    record_event(Event::Updated {
+        id: todo.id,
+        at: todo.updated_at,
+    });
+}
+
+/// Appends `event` to the event log and evicts the oldest entries beyond `MAX_EVENTS`.
+fn record_event(event: Event) {
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let next = events.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        events.insert(next, event);
+        while events.len() > MAX_EVENTS {
+            events.pop_first();
+        }
+    })
+}
+
+/// Returns all todos belonging to `owner`, ordered by ID.
+fn owned_todos(todomap: &TodoDB, owner: Principal) -> Vec<Todo> {
+    todomap.load(todomap.owned_ids(owner, 0))
+}
+
+/// Returns all todos `reader` may read, its own and those shared with it, ordered by ID.
+fn visible_todos(todomap: &TodoDB, reader: Principal) -> Vec<Todo> {
+    todomap.load(visible_ids(todomap, reader, 0, usize::MAX))
+}
+
+/// Returns up to `limit` IDs of todos `reader` may read, in increasing order starting at `from`.
+fn visible_ids(todomap: &TodoDB, reader: Principal, from: u64, limit: usize) -> Vec<u64> {
+    let mut ids: Vec<u64> = todomap
+        .owned_ids(reader, from)
+        .take(limit)
+        .chain(todomap.shared_ids(reader, from).take(limit))
+        .collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids.truncate(limit);
+    ids
+}
+
+/// Trims the title of a todo and makes sure it is neither empty nor too long.
+///
+/// The length is counted in characters, not bytes, so multi-byte characters count once.
+/// Returns the trimmed title on success, so leading and trailing whitespace is never stored.
+/// If the admin turned it on with `set_collapse_whitespace`, whitespace inside the title
+/// is collapsed too; the length is checked after collapsing.
+fn validate_title(title: &str) -> Result<String, TodoError> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(TodoError::EmptyContent);
+    }
+    let title = if config().collapse_whitespace {
+        collapse_whitespace(title)
+    } else {
+        title.to_owned()
+    };
+    if title.chars().count() > MAX_TODO_LEN {
+        return Err(TodoError::TooLong(MAX_TODO_LEN));
+    }
+    Ok(title)
+}
+
+/// Replaces every run of whitespace other than line breaks in `text` by a single space.
+/// Line breaks, `\n` as well as `\r`, are kept as they are.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_run = false;
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\n' && c != '\r' {
+            if !in_run {
+                collapsed.push(' ');
+            }
+            in_run = true;
+        } else {
+            collapsed.push(c);
+            in_run = false;
+        }
+    }
+    collapsed
+}
+
+/// Trims the description of a todo and makes sure it is not too long, it may be empty.
+fn validate_description(description: &str) -> Result<String, TodoError> {
+    let description = description.trim();
+    if description.chars().count() > MAX_DESCRIPTION_LEN {
+        return Err(TodoError::TooLong(MAX_DESCRIPTION_LEN));
+    }
+    Ok(description.to_owned())
+}
+
+/// Increments `GEN_ID` and returns the new value as the ID for the next todo.
+fn next_todo_id() -> u64 {
+    GEN_ID.with(|tid| {
+        let mut borrowed = tid.borrow_mut();
+        let new_tid = *borrowed.get() + 1;
+        borrowed
+            .set(new_tid)
+            .expect("Failed to persist the todo ID counter");
+        new_tid
+    })
+}
+
+/// Returns the ID for a new todo: the smallest freed ID if reuse is on, a fresh one otherwise.
+///
+/// A reused ID is dropped from the trash, so `undo_delete` can't overwrite the new todo.
+fn assign_todo_id() -> u64 {
+    let reused = config()
+        .reuse_ids
+        .then(|| {
+            FREE_IDS.with(|free_ids| {
+                let mut free_ids = free_ids.borrow_mut();
+                let id = free_ids.iter().next().map(|(id, _)| id)?;
+                free_ids.remove(&id);
+                Some(id)
+            })
+        })
+        .flatten();
+    match reused {
+        Some(id) => {
+            TRASH.with(|trash| trash.borrow_mut().remove(&id));
+            id
+        }
+        None => next_todo_id(),
+    }
+}
+
+/// Creates a new todo and returns its unique ID.
+///
+/// This update function adds a new todo with the provided title and description to the internal storage.
+/// The caller becomes the owner of the todo. New todos always start out as not done
+/// and with `Priority::Medium`, use `create` to set more fields right away.
+/// It assigns a unique identifier to the new todo and returns it.
+///
+/// # Parameters
+///
+/// * `title` (String): The title of the new todo. Surrounding whitespace is trimmed, inner
+///   whitespace may be collapsed, see `set_collapse_whitespace`.
+/// * `description` (String): Notes for the new todo, may be empty. Surrounding whitespace is trimmed.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success:  It returns the ID for the newly created todo.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `EmptyContent`: If the title is empty or only consists of whitespace.
+/// * `TooLong(MAX_TODO_LEN)`: If the title is longer than `MAX_TODO_LEN` (1000) characters.
+/// * `TooLong(MAX_DESCRIPTION_LEN)`: If the description is longer than
+///   `MAX_DESCRIPTION_LEN` (10000) characters.
+/// * `Duplicate`: If dedup mode is on and the caller already has a todo with this title.
+/// * `LimitReached(max_todos)`: If the canister already stores `max_todos` todos.
+/// * `RateLimited`: If the caller already created `max_adds_per_minute` todos within the
+///   current minute, in any way.
+#[update(name = "add")]
+fn add_todo(title: String, description: String) -> Result<u64, TodoError> {
+    create(CreateTodo {
+        title,
+        description,
+        tags: Vec::new(),
+        priority: Priority::Medium,
+        due_at: None,
+    })
+}
+
+/// Creates a new todo with tags, priority and due date and returns its unique ID.
+///
+/// Works like `add`, but sets the extra fields in the same call. All fields are validated
+/// before anything is stored.
+///
+/// # Parameters
+///
+/// * `input` (CreateTodo): The fields of the new todo. The title and description are
+///                         trimmed, the tags normalized like in `add_tag`.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success:  It returns the ID for the newly created todo.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return the errors of `add`, and `EmptyContent` if one of the tags is
+/// empty or only whitespace.
+#[update(name = "create")]
+fn create(input: CreateTodo) -> Result<u64, TodoError> {
+    let title = validate_title(&input.title)?;
+    let description = validate_description(&input.description)?;
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.tags.iter().map(|tag| normalize_tag(tag)) {
+        if tag.is_empty() {
+            return Err(TodoError::EmptyContent);
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    let owner = caller();
+    if dedup_titles(owner).is_some_and(|titles| titles.contains(&title)) {
+        return Err(TodoError::Duplicate);
+    }
+    ensure_capacity(1)?;
+    check_rate_limit(owner, 1)?;
+
+    let mut todo = new_todo(title, description, owner);
+    todo.tags = tags;
+    todo.priority = input.priority;
+    todo.due_at = input.due_at;
+    Ok(TODOMAP.with(|todomap| store_new_todo(&mut todomap.borrow_mut(), todo)))
+}
+
+/// Returns the ID of the caller's todo with this title, creating it with `add` if there is none.
+///
+/// Retrying the call after a timeout therefore never creates a second todo. The title is
+/// normalized like in `add` before it is compared, so `  Buy milk ` finds `Buy milk`. If
+/// several todos match, the one with the smallest ID is returned.
+///
+/// # Parameters
+///
+/// * `title` (String): The title of the todo.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the ID of the existing or newly created todo.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return the errors of `add`, but only if no todo with this title exists.
+#[update(name = "get_or_create")]
+fn get_or_create(title: String) -> Result<u64, TodoError> {
+    let normalized = validate_title(&title)?;
+    let existing = TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .find(|todo| todo.title == normalized)
+            .map(|todo| todo.id)
+    });
+    match existing {
+        Some(id) => Ok(id),
+        None => add_todo(title, String::new()),
+    }
+}
+
+/// Returns the ID the next successful `add` will assign, so clients can render a new todo
+/// before the update completes.
+///
+/// This is only a prediction: any todo created in between, by this or another principal,
+/// takes the ID first. Clients must use the ID returned by `add` in the end.
+///
+/// # Returns
+///
+/// The smallest freed ID while ID reuse is on and there is one, see `set_reuse_ids`,
+/// and the ID counter plus one otherwise.
+#[query(name = "next_id")]
+fn next_id() -> u64 {
+    let reused = config()
+        .reuse_ids
+        .then(|| FREE_IDS.with(|free_ids| free_ids.borrow().iter().next().map(|(id, _)| id)))
+        .flatten();
+    reused.unwrap_or_else(|| GEN_ID.with(|tid| *tid.borrow().get() + 1))
+}
+
+/// Stores a new todo with an already validated title and description and returns its ID.
+fn insert_new_todo(title: String, description: String, owner: Principal) -> u64 {
+    let todo = new_todo(title, description, owner);
+    TODOMAP.with(|todomap| store_new_todo(&mut todomap.borrow_mut(), todo))
+}
+
+/// Stores a todo built by `new_todo` and then notifies the subscriber, returns its ID.
+///
+/// Every way of creating todos stores them through this, so the subscriber hears of each
+/// new todo exactly once and only after it exists.
+fn store_new_todo(todomap: &mut TodoDB, todo: Todo) -> u64 {
+    let id = todo.id;
+    let title = todo.title.clone();
+    todomap.insert(id, todo);
+    notify_subscriber(id, &title);
+    id
+}
+
+/// Builds a todo with a freshly assigned ID and default values for everything else.
+///
+/// The todo is logged as added, so callers must store it.
+fn new_todo(title: String, description: String, owner: Principal) -> Todo {
+    let todo = todo_with_id(assign_todo_id(), title, description, owner);
+    record_event(Event::Added {
+        id: todo.id,
+        at: todo.created_at,
+    });
+    todo
+}
+
+/// Builds a todo with the given ID and default values for everything else, logging nothing.
+fn todo_with_id(id: u64, title: String, description: String, owner: Principal) -> Todo {
+    let created_at = now();
+    Todo {
+        id,
+        owner,
+        shared_with: Vec::new(),
+        title,
+        description,
+        done: false,
+        completed_at: None,
+        priority: Priority::Medium,
+        due_at: None,
+        recurrence: None,
+        tags: Vec::new(),
+        // The ID counter only grows and `reorder` never hands out positions above the number
+        // of todos, so starting from the counter puts new todos at the end of the list.
+        order: u32::try_from(GEN_ID.with(|tid| *tid.borrow().get())).unwrap_or(u32::MAX),
+        pinned: false,
+        starred: false,
+        color: None,
+        parent_id: None,
+        created_at,
+        updated_at: created_at,
+        version: 0,
+    }
+}
+
+/// Creates several todos in one call and returns their IDs.
+///
+/// The batch is atomic: every entry is validated like in `add` before anything is
+/// stored, so either all todos are created or none. The new todos have an empty description.
+/// In dedup mode an entry also counts as a duplicate of earlier entries of the same batch.
+///
+/// # Parameters
+///
+/// * `titles` (Vec<String>): The titles of the new todos.
+///
+/// # Returns
+///
+/// A `Result<Vec<u64>, TodoError>`.
+/// On success: It returns the IDs of the new todos, in the same order as `titles`.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `InvalidEntry { index, error }`: For the first entry that fails validation, where
+///   `error` is the error `add` would have returned.
+/// * `LimitReached(max_todos)`: If the whole batch doesn't fit below `max_todos`.
+/// * `RateLimited`: If the whole batch doesn't fit into the caller's `max_adds_per_minute`.
+#[update(name = "add_many")]
+fn add_many(titles: Vec<String>) -> Result<Vec<u64>, TodoError> {
+    let owner = caller();
+    let mut seen = dedup_titles(owner);
+    let titles = titles
+        .iter()
+        .enumerate()
+        .map(|(index, title)| {
+            validate_title(title)
+                .and_then(|title| {
+                    let is_new = seen.as_mut().is_none_or(|seen| seen.insert(title.clone()));
+                    if is_new {
+                        Ok(title)
+                    } else {
+                        Err(TodoError::Duplicate)
+                    }
+                })
+                .map_err(|error| TodoError::InvalidEntry {
+                    index: index as u64,
+                    error: Box::new(error),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    ensure_capacity(titles.len() as u64)?;
+    check_rate_limit(owner, titles.len() as u64)?;
+    Ok(titles
+        .into_iter()
+        .map(|title| insert_new_todo(title, String::new(), owner))
+        .collect())
+}
+
+/// Creates a copy of an existing todo and returns the ID of the copy.
+///
+/// The copy gets the title, description, tags and priority of the original. Everything
+/// else starts fresh like with `add`: it is not done, not pinned, not starred, has no due
+/// date and no parent, and its timestamps are set to now. Later changes to either todo don't affect
+/// the other one.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to copy.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success:  It returns the ID of the copy.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `LimitReached(max_todos)`: If the canister already stores `max_todos` todos.
+/// * `RateLimited`: Like in `add`.
+#[update(name = "duplicate")]
+fn duplicate(id: u64) -> Result<u64, TodoError> {
+    let owner = caller();
+    let original = TODOMAP
+        .with(|todomap| get_owned(&todomap.borrow(), id, owner))
+        .ok_or(TodoError::NotFound(id))?;
+    ensure_capacity(1)?;
+    check_rate_limit(owner, 1)?;
+    let mut copy = new_todo(original.title, original.description, owner);
+    copy.tags = original.tags;
+    copy.priority = original.priority;
+    Ok(TODOMAP.with(|todomap| store_new_todo(&mut todomap.borrow_mut(), copy)))
+}
+
+/// Creates a subtask of an existing todo and returns its unique ID.
+///
+/// Subtasks are regular todos with `parent_id` set, so they show up in `read_all` as
+/// well. Subtasks can have subtasks of their own.
+///
+/// # Parameters
+///
+/// * `parent_id` (u64): The unique identifier of the parent todo.
+/// * `title` (String): The title of the new subtask. Surrounding whitespace is trimmed.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success:  It returns the ID for the newly created subtask.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(parent_id)`: If the parent doesn't exist.
+/// * `EmptyContent`: If the title is empty or only consists of whitespace.
+/// * `TooLong(MAX_TODO_LEN)`: If the title is longer than `MAX_TODO_LEN` (1000) characters.
+/// * `LimitReached(max_todos)`: If the canister already stores `max_todos` todos.
+/// * `RateLimited`: Like in `add`.
+#[update(name = "add_subtask")]
+fn add_subtask(parent_id: u64, title: String) -> Result<u64, TodoError> {
+    let title = validate_title(&title)?;
+    ensure_capacity(1)?;
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        if get_owned(&todomap, parent_id, owner).is_none() {
+            return Err(TodoError::NotFound(parent_id));
+        }
+        check_rate_limit(owner, 1)?;
+        let mut todo = new_todo(title, String::new(), owner);
+        todo.parent_id = Some(parent_id);
+        Ok(store_new_todo(&mut todomap, todo))
+    })
+}
+
+/// Lists the direct subtasks of a todo.
+///
+/// # Parameters
+///
+/// * `parent_id` (u64): The unique identifier of the parent todo.
+///
+/// # Returns
+///
+/// The subtasks ordered by ID, or an empty array if the todo has none or doesn't exist.
+#[query(name = "children")]
+fn children(parent_id: u64) -> Vec<Todo> {
+    TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| todo.parent_id == Some(parent_id))
+            .collect()
+    })
+}
+
+/// Reads a specific todo by its ID.
+///
+/// This query function retrieves the todo identified by the provided ID.
+/// Todos owned by other principals are treated as if they didn't exist, unless they are
+/// shared with the caller, see `share`.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be read.
+///
+/// # Returns
+///
+/// A `Result<Todo, TodoError>`.
+/// On success: It returns the todo record, including its ID, title and description.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[query(name = "read")]
+fn read_todo(id: u64) -> Result<Todo, TodoError> {
+    TODOMAP
+        .with(|todomap| get_visible(&todomap.borrow(), id, caller()).ok_or(TodoError::NotFound(id)))
+}
+
+/// Reads a todo like `read` and adds the certificate of the canister's certified data.
+///
+/// The certified data is the hash of all stored todos, see `TodoDB`, so the certificate
+/// proves the state of the whole store at the time of the query rather than this todo
+/// alone. Certificates only exist in query calls, called as an update the certificate
+/// is empty.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be read.
+///
+/// # Returns
+///
+/// A `Result<(Todo, Vec<u8>), TodoError>`.
+/// On success: It returns the todo record and the CBOR encoded certificate.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[query(name = "read_certified")]
+fn read_certified(id: u64) -> Result<(Todo, Vec<u8>), TodoError> {
+    let todo = read_todo(id)?;
+    Ok((todo, data_certificate()))
+}
+
+/// Composite query variant of `read`, so other canisters can compose reads of
+/// this canister from their own composite queries.
+///
+/// Behaves exactly like `read`, including caller scoping and the
+/// `TodoError::NotFound(id)` error.
+#[query(composite = true, name = "read_composite")]
+fn read_todo_composite(id: u64) -> Result<Todo, TodoError> {
+    read_todo(id)
+}
+
+/// Checks whether the caller can read a todo with the given ID, without transferring it.
+///
+/// Todos owned by other principals are reported as missing, unless they are shared with
+/// the caller, like in `read`.
+#[query(name = "exists")]
+fn exists(id: u64) -> bool {
+    TODOMAP.with(|todomap| get_visible(&todomap.borrow(), id, caller()).is_some())
+}
+
+/// Returns the principal the canister sees as the caller, the owner of the todos it adds.
+///
+/// Anonymous calls, e.g. from `dfx` without an identity, get the anonymous principal
+/// `2vxsx-fae`, which all anonymous callers share.
+#[query(name = "whoami")]
+fn whoami() -> Principal {
+    caller()
+}
+
+/// Maps a requested page size to the one actually used.
+///
+/// `0` selects the configured default page size, values above `MAX_PAGE_SIZE` are clamped.
+fn resolve_page_size(page_size: u16) -> u16 {
+    match page_size {
+        0 => config().default_page_size,
+        size => size.min(MAX_PAGE_SIZE),
+    }
+}
+
+/// Cuts page number `page` (starting from 1, `0` counts as 1) of `page_size` items out of `items`.
+///
+/// Returns the items on that page, which is empty past the end, and the number of the
+/// next page, `None` if there are no items beyond the requested page.
+///
+/// The offsets are computed as `usize`, so large page numbers cannot overflow.
+fn paginate<T: Clone>(items: &[T], page: u16, page_size: u16) -> (Vec<T>, Option<u16>) {
+    let page = page.max(1);
+    let start_index = (page as usize - 1) * page_size as usize;
+    let end_index = start_index + page_size as usize;
+
+    let slice: Vec<T> = items
+        .iter()
+        .skip(start_index)
+        .take(page_size as usize)
+        .cloned()
+        .collect();
+
+    let next_page = if items.len() > end_index {
+        page.checked_add(1)
+    } else {
+        None
+    };
+
+    (slice, next_page)
+}
+
+/// Reads several todos by their IDs in one query.
+///
+/// # Parameters
+///
+/// * `ids` (Vec<u64>): The unique identifiers of the todos to be read.
+///
+/// # Returns
+///
+/// One entry per requested ID, in the same order: the todo, or `None` if there is no
+/// todo with this ID or `read` would reject it.
+#[query(name = "read_many")]
+fn read_many(ids: Vec<u64>) -> Vec<Option<Todo>> {
+    let reader = caller();
+    TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        ids.into_iter()
+            .map(|id| get_visible(&todomap, id, reader))
+            .collect()
+    })
+}
+
+/// Reads the caller's todos with IDs between `start` and `end`, both inclusive.
+///
+/// Meant for clients syncing deltas. Only the caller's part of the owner index is visited.
+///
+/// # Parameters
+///
+/// * `start` (u64): The smallest ID to return.
+/// * `end` (u64): The largest ID to return.
+///
+/// # Returns
+///
+/// The caller's todos in the range, ordered by ID. Empty if `start` is greater than `end`.
+#[query(name = "read_range")]
+fn read_range(start: u64, end: u64) -> Vec<Todo> {
+    if start > end {
+        return Vec::new();
+    }
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        todomap.load(todomap.owned_ids(owner, start).take_while(|&id| id <= end))
+    })
+}
+
+/// Lists the caller's todos by ID, continuing after a cursor instead of a page number.
+///
+/// Unlike `read_all`, adding or deleting todos between two calls neither skips nor repeats
+/// a todo, since every call continues right after the last ID the client has seen. Shared
+/// todos are included like in `read_all`.
+///
+/// # Parameters
+///
+/// * `after_id` (Option<u64>): The cursor returned by the previous call, `None` to start
+///                             from the first todo.
+/// * `limit` (u16): The maximum number of todos to return. `0` selects the default page
+///                  size, values above `MAX_PAGE_SIZE` are clamped.
+///
+/// # Returns
+///
+/// The todos with IDs greater than `after_id`, ordered by ID, and the cursor for the next
+/// call: the ID of the last returned todo, or `None` if there are no more todos.
+#[query(name = "list")]
+fn list(after_id: Option<u64>, limit: u16) -> (Vec<Todo>, Option<u64>) {
+    let reader = caller();
+    let limit = resolve_page_size(limit) as usize;
+    let start = match after_id {
+        Some(u64::MAX) => return (Vec::new(), None),
+        Some(after_id) => after_id + 1,
+        None => 0,
+    };
+    let mut todos: Vec<Todo> = TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        todomap.load(visible_ids(&todomap, reader, start, limit + 1))
+    });
+    let cursor = if todos.len() > limit {
+        todos.truncate(limit);
+        todos.last().map(|todo| todo.id)
+    } else {
+        None
+    };
+    (todos, cursor)
+}
+
+/// Retrieves a paginated list of the caller's todos.
+///
+/// Todos other principals shared with the caller are listed along with the caller's own.
+/// This query function retrieves a maximum of `page_size` todos per page.
+/// Pinned todos come first, then the unpinned ones, each group ordered by `sort`.
+/// Todos that compare equal stay ordered by ID.
+///
+/// Every call materializes all of the caller's todos. Sorting by anything but ID additionally
+/// sorts all of them before paging, which is O(n log n).
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+/// * `page_size` (u16): The number of todos per page. `0` selects the `default_page_size`
+///                       passed at install time, values above `MAX_PAGE_SIZE` (100) are clamped.
+/// * `sort` (Option<SortBy>): The order of the todos, `None` means `SortBy::IdAsc`.
+///
+/// # Returns
+///
+/// A `Page` containing:
+///
+/// * `items`: An array of the todo records on the requested page.
+/// * `next_page`: An optional value indicating the next page number (if applicable).
+///                If there are no more todos beyond the current page, this will be `None`.
+/// * `total_items`: The total number of todos the caller owns or is shared on.
+/// * `total_pages`: The number of pages for the chosen page size.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)` if `page` is past the last page,
+/// which is every page when the caller has no todos. Page `0` is read as page 1.
+#[query(name = "read_all")]
+fn read_all_todos(page: u16, page_size: u16, sort: Option<SortBy>) -> Result<Page, TodoError> {
+    let mut owned = TODOMAP.with(|todomap| visible_todos(&todomap.borrow(), caller()));
+    // the todos come ordered by ID and all sorts are stable, so ties keep that order
+    match sort.unwrap_or_default() {
+        SortBy::IdAsc => {}
+        SortBy::IdDesc => owned.reverse(),
+        SortBy::CreatedAsc => owned.sort_by_key(|todo| todo.created_at),
+        SortBy::CreatedDesc => owned.sort_by_key(|todo| std::cmp::Reverse(todo.created_at)),
+        SortBy::TitleAsc => owned.sort_by_cached_key(|todo| todo.title.to_lowercase()),
+    }
+    owned.sort_by_key(|todo| !todo.pinned);
+    build_page(&owned, page, resolve_page_size(page_size))
+}
+
+/// Pages `todos` like `read_all` and fills in the totals.
+///
+/// Page `0` is read as page 1, any page past `total_pages` is rejected with
+/// `TodoError::InvalidPage`.
+fn build_page(todos: &[Todo], page: u16, limit: u16) -> Result<Page, TodoError> {
+    let page = page.max(1);
+    let total_items = todos.len() as u64;
+    let total_pages = total_items.div_ceil(limit as u64).min(u16::MAX as u64) as u16;
+    if page > total_pages {
+        return Err(TodoError::InvalidPage(page));
+    }
+
+    let (items, next_page) = paginate(todos, page, limit);
+
+    Ok(Page {
+        items,
+        next_page,
+        total_items,
+        total_pages,
+    })
+}
+
+/// Retrieves a page of the caller's todos as `(id, title)` pairs, for clients that only
+/// need the titles.
+///
+/// The todos are ordered and paged like `read_all` with the default page size and sort.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A `Result<TitlePage, TodoError>`.
+/// On success: It returns the pairs on the requested page and the next page number, `None`
+///             on the last page.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If `page` is past the last page.
+#[query(name = "read_all_with_ids")]
+fn read_all_with_ids(page: u16) -> Result<TitlePage, TodoError> {
+    let page = read_all_todos(page, 0, None)?;
+    let titles = page
+        .items
+        .into_iter()
+        .map(|todo| (todo.id, todo.title))
+        .collect();
+    Ok((titles, page.next_page))
+}
+
+/// Retrieves a paginated list of the caller's todos, most important first.
+///
+/// Todos are ordered from `Priority::High` to `Priority::Low`, todos with the same
+/// priority by ID. Pages hold `DEFAULT_PAGE_SIZE` (10) todos. Shared todos are included
+/// like in `read_all`.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A `Page` like `read_all`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If no todos are found on the requested page.
+#[query(name = "read_all_by_priority")]
+fn read_all_by_priority(page: u16) -> Result<Page, TodoError> {
+    let mut owned = TODOMAP.with(|todomap| visible_todos(&todomap.borrow(), caller()));
+    owned.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.id.cmp(&b.id)));
+    build_page(&owned, page, DEFAULT_PAGE_SIZE)
+}
+
+/// Retrieves a paginated list of the caller's todos in their manual order.
+///
+/// Todos are sorted by `order`, see `reorder`. New todos are appended at the end.
+/// Pages hold `DEFAULT_PAGE_SIZE` (10) todos. Shared todos are included like in `read_all`,
+/// at the position their owner gave them.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A `Page` like `read_all`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If no todos are found on the requested page.
+#[query(name = "read_all_ordered")]
+fn read_all_ordered(page: u16) -> Result<Page, TodoError> {
+    let mut owned = TODOMAP.with(|todomap| visible_todos(&todomap.borrow(), caller()));
+    owned.sort_by_key(|todo| (todo.order, todo.id));
+    build_page(&owned, page, DEFAULT_PAGE_SIZE)
+}
+
+/// Moves a todo to another position of the caller's manual order.
+///
+/// The other todos shift to make room, afterwards the caller's todos are numbered
+/// `0, 1, 2, ...` in their new order. Moving a todo to the position it already has
+/// changes nothing.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to move.
+/// * `new_position` (u32): The new position, starting from 0. Positions past the end
+///                         move the todo to the end.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "reorder")]
+fn reorder(id: u64, new_position: u32) -> Result<(), TodoError> {
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let mut owned = owned_todos(&todomap, caller());
+        owned.sort_by_key(|todo| (todo.order, todo.id));
+        let index = owned
+            .iter()
+            .position(|todo| todo.id == id)
+            .ok_or(TodoError::NotFound(id))?;
+
+        let mut moved = owned.remove(index);
+        let new_position = (new_position as usize).min(owned.len());
+        if new_position == index {
+            return Ok(());
+        }
+        touch(&mut moved);
+        owned.insert(new_position, moved);
+
+        for (position, mut todo) in owned.into_iter().enumerate() {
+            let position = position as u32;
+            if todo.order != position || todo.id == id {
+                todo.order = position;
+                todomap.insert(todo.id, todo);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Exchanges the positions of two todos in the caller's manual order, see `reorder`.
+///
+/// Afterwards the caller's todos are numbered `0, 1, 2, ...` like after `reorder`.
+/// Swapping a todo with itself changes nothing.
+///
+/// # Parameters
+///
+/// * `a` (u64): The unique identifier of the first todo.
+/// * `b` (u64): The unique identifier of the second todo.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If either ID is invalid or the todo doesn't exist, nothing is changed then.
+#[update(name = "swap")]
+fn swap(a: u64, b: u64) -> Result<(), TodoError> {
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let mut owned = owned_todos(&todomap, caller());
+        owned.sort_by_key(|todo| (todo.order, todo.id));
+        let position = |id| {
+            owned
+                .iter()
+                .position(|todo: &Todo| todo.id == id)
+                .ok_or(TodoError::NotFound(id))
+        };
+        let (index_a, index_b) = (position(a)?, position(b)?);
+        if index_a == index_b {
+            return Ok(());
+        }
+        owned.swap(index_a, index_b);
+
+        for (position, mut todo) in owned.into_iter().enumerate() {
+            let position = position as u32;
+            if todo.id == a || todo.id == b {
+                touch(&mut todo);
+            } else if todo.order == position {
+                continue;
+            }
+            todo.order = position;
+            todomap.insert(todo.id, todo);
+        }
+        Ok(())
+    })
+}
+
+/// Moves a todo to the front of the caller's manual order, see `reorder`.
+///
+/// Does nothing if the todo already comes first.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "move_to_top")]
+fn move_to_top(id: u64) -> Result<(), TodoError> {
+    reorder(id, 0)
+}
+
+/// Moves a todo to the end of the caller's manual order, see `reorder`.
+///
+/// Does nothing if the todo already comes last.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "move_to_bottom")]
+fn move_to_bottom(id: u64) -> Result<(), TodoError> {
+    reorder(id, u32::MAX)
+}
+
+/// Updates the title of an existing todo.
+///
+/// This update function modifies the title of a todo identified by the provided ID with the new title.
+/// The description is left untouched, see `update_description`.
+///
+/// The update only goes through if the todo is still at `expected_version`, so two clients
+/// editing the same todo cannot silently overwrite each other. On a conflict the client
+/// should read the todo again and resubmit.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be updated.
+/// * `new_title` (String): The new title for the todo. Surrounding whitespace is trimmed.
+/// * `expected_version` (u64): The `version` of the todo the client based its change on.
+///
+/// # Returns
+///
+/// A `Result<String, TodoError>`.
+/// On success: It returns the title that was replaced, so clients can offer an undo.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `EmptyContent`: If the new title is empty or only consists of whitespace.
+/// * `TooLong(MAX_TODO_LEN)`: If the new title is longer than `MAX_TODO_LEN` (1000) characters.
+/// * `VersionConflict { current }`: If the todo is no longer at `expected_version`.
+#[update(name = "update")]
+fn update_todo(id: u64, new_title: String, expected_version: u64) -> Result<String, TodoError> {
+    let new_title = validate_title(&new_title)?;
+    with_todo_mut(id, |todo| {
+        if todo.version != expected_version {
+            return Err(TodoError::VersionConflict {
+                current: todo.version,
+            });
+        }
+        Ok(std::mem::replace(&mut todo.title, new_title))
+    })
+}
+
+/// Updates the description of an existing todo.
+///
+/// This update function only changes the notes of a todo, the title is left untouched.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be updated.
+/// * `new_description` (String): The new description, may be empty. Surrounding whitespace is trimmed.
+///
+/// # Returns
+///
+/// A `Result<String, TodoError>`.
+/// On success: It returns the description that was replaced.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `TooLong(MAX_DESCRIPTION_LEN)`: If the new description is longer than
+///   `MAX_DESCRIPTION_LEN` (10000) characters.
+#[update(name = "update_description")]
+fn update_description(id: u64, new_description: String) -> Result<String, TodoError> {
+    let new_description = validate_description(&new_description)?;
+    with_todo_mut(id, |todo| {
+        Ok(std::mem::replace(&mut todo.description, new_description))
+    })
+}
+
+/// Changes the titles of several todos in one call.
+///
+/// The batch is atomic like `add_many`: every entry is validated before anything is
+/// changed, so either all titles are changed or none. Unlike `update` there is no version
+/// check. If an ID appears more than once, the last entry wins and the todo still counts
+/// as changed only once.
+///
+/// # Parameters
+///
+/// * `edits` (Vec<(u64, String)>): Pairs of the ID of a todo and its new title.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the number of changed todos.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidEntry { index, error }` for the first entry
+/// that fails validation, where `error` is the error `update` would have returned:
+/// `NotFound(id)`, `EmptyContent` or `TooLong(MAX_TODO_LEN)`.
+#[update(name = "update_many")]
+fn update_many(edits: Vec<(u64, String)>) -> Result<u64, TodoError> {
+    let owner = caller();
+    let edits = TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        edits
+            .iter()
+            .enumerate()
+            .map(|(index, (id, title))| {
+                get_owned(&todomap, *id, owner)
+                    .ok_or(TodoError::NotFound(*id))
+                    .and_then(|_| validate_title(title))
+                    .map(|title| (*id, title))
+                    .map_err(|error| TodoError::InvalidEntry {
+                        index: index as u64,
+                        error: Box::new(error),
+                    })
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()
+    })?;
+    for (id, title) in &edits {
+        with_todo_mut(*id, |todo| {
+            todo.title.clone_from(title);
+            Ok(())
+        })?;
+    }
+    Ok(edits.len() as u64)
+}
+
+/// Deletes a todo by its ID.
+///
+/// This update function removes a todo identified by the provided ID from the internal storage
+/// and moves it to the trash, from where `undo_delete` can bring it back.
+///
+/// Deleting cascades: all subtasks of the todo, and their subtasks, are deleted and moved
+/// to the trash as well. `undo_delete` restores them one by one.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be deleted.
+///
+/// # Returns
+///
+/// A `Result<Todo, TodoError>`.
+/// On success: it returns the deleted todo, so clients can show what was removed or offer an undo.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "delete")]
+fn delete_todo(id: u64) -> Result<Todo, TodoError> {
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        match get_owned(&todomap, id, caller()) {
+            Some(todo) => {
+                remove_with_subtasks(&mut todomap, id);
+                Ok(todo)
+            }
+            None => Err(TodoError::NotFound(id)),
+        }
+    })
+}
+
+/// Deletes several todos by their IDs.
+///
+/// Unknown IDs, and IDs of todos owned by other principals, are silently ignored, so
+/// the call is idempotent: repeating it deletes nothing more and simply returns fewer IDs.
+/// Deleted todos are moved to the trash and their subtasks are deleted as well, like with `delete`.
+///
+/// # Parameters
+///
+/// * `ids` (Vec<u64>): The unique identifiers of the todos to be deleted.
+///
+/// # Returns
+///
+/// The IDs that existed and were deleted, in the order they were given.
+#[update(name = "delete_many")]
+fn delete_many(ids: Vec<u64>) -> Vec<u64> {
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        ids.into_iter()
+            .filter(|id| {
+                get_owned(&todomap, *id, owner).is_some() && remove_with_subtasks(&mut todomap, *id)
+            })
+            .collect()
+    })
+}
+
+/// Hands a todo over to another principal.
+///
+/// The todo keeps its ID and content, but from now on only `to` can see and change it:
+/// it is no longer shared with anyone and goes to the end of the receiver's manual order.
+/// Its subtasks, at any depth, move along with it. If the todo is itself a subtask, it
+/// becomes a top-level todo of the receiver.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to hand over.
+/// * `to` (Principal): The new owner.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't belong to the caller.
+/// * `InvalidInput(message)`: If `to` is the anonymous principal, which nobody controls,
+///   or the caller itself.
+#[update(name = "transfer")]
+fn transfer(id: u64, to: Principal) -> Result<(), TodoError> {
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        if get_owned(&todomap, id, owner).is_none() {
+            return Err(TodoError::NotFound(id));
+        }
+        if to == Principal::anonymous() {
+            return Err(TodoError::InvalidInput(
+                "Todos can't be transferred to the anonymous principal".to_owned(),
+            ));
+        }
+        if to == owner {
+            return Err(TodoError::InvalidInput(
+                "The todo already belongs to the caller".to_owned(),
+            ));
+        }
+        let mut order = owned_todos(&todomap, to)
+            .iter()
+            .map(|todo| todo.order.saturating_add(1))
+            .max()
+            .unwrap_or(0);
+        let mut pending = vec![id];
+        while let Some(current) = pending.pop() {
+            pending.extend(
+                todomap
+                    .iter()
+                    .filter(|(_, child)| child.parent_id == Some(current))
+                    .map(|(child_id, _)| child_id),
+            );
+            let mut todo = todomap.get(&current).expect("The todo was just listed");
+            todo.owner = to;
+            todo.shared_with.clear();
+            todo.order = order;
+            order = order.saturating_add(1);
+            if current == id {
+                todo.parent_id = None;
+            }
+            touch(&mut todo);
+            todomap.insert(current, todo);
+        }
+        Ok(())
+    })
+}
+
+/// Applies `f` to the principals one of the caller's todos is shared with and stores the result.
+fn edit_shared_with(id: u64, f: impl FnOnce(&mut Vec<Principal>)) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        f(&mut todo.shared_with);
+        Ok(())
+    })
+}
+
+/// Gives another principal read access to a todo.
+///
+/// The principal sees the todo in `read` and `read_all`, but can't change it. Only the
+/// owner may share a todo. Sharing with the owner or twice with the same principal does
+/// nothing.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to share.
+/// * `principal` (Principal): The principal to share the todo with.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't belong to the caller.
+#[update(name = "share")]
+fn share(id: u64, principal: Principal) -> Result<(), TodoError> {
+    let owner = caller();
+    edit_shared_with(id, |shared_with| {
+        if principal != owner && !shared_with.contains(&principal) {
+            shared_with.push(principal);
+        }
+    })
+}
+
+/// Takes back the read access `share` gave to a principal.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the shared todo.
+/// * `principal` (Principal): The principal to stop sharing the todo with.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo wasn't shared with `principal`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't belong to the caller.
+#[update(name = "unshare")]
+fn unshare(id: u64, principal: Principal) -> Result<(), TodoError> {
+    edit_shared_with(id, |shared_with| shared_with.retain(|p| *p != principal))
+}
+
+/// Removes the todo `id` and all of its subtasks, at any depth, and moves them to the trash.
+///
+/// Returns whether the todo existed.
+fn remove_with_subtasks(todomap: &mut TodoDB, id: u64) -> bool {
+    let mut pending = vec![id];
+    let mut removed_any = false;
+    while let Some(id) = pending.pop() {
+        if let Some(todo) = todomap.remove(&id) {
+            removed_any = true;
+            record_event(Event::Deleted { id, at: now() });
+            free_id(id);
+            pending.extend(
+                todomap
+                    .iter()
+                    .filter(|(_, child)| child.parent_id == Some(id))
+                    .map(|(child_id, _)| child_id),
+            );
+            move_to_trash(todo);
+        }
+    }
+    removed_any
+}
+
+/// Puts a deleted todo into the trash and evicts the oldest entries of its owner beyond
+/// `MAX_TRASH_LEN`, so one principal deleting a lot can't push out the todos of others.
+fn move_to_trash(todo: Todo) {
+    TRASH.with(|trash| {
+        let mut trash = trash.borrow_mut();
+        let owner = todo.owner;
+        let trashed = TrashedTodo {
+            todo,
+            deleted_at: now(),
+        };
+        trash.insert(trashed.todo.id, trashed);
+        let mut owned: Vec<(u64, u64)> = trash
+            .iter()
+            .filter(|(_, trashed)| trashed.todo.owner == owner)
+            .map(|(id, trashed)| (trashed.deleted_at, id))
+            .collect();
+        let excess = owned.len().saturating_sub(MAX_TRASH_LEN as usize);
+        owned.sort_unstable();
+        for (_, id) in &owned[..excess] {
+            trash.remove(id);
+        }
+    })
+}
+
+/// Restores a deleted todo from the trash.
+///
+/// The todo comes back with its original ID, title and description. Only the most recently deleted
+/// todos of each principal are kept, see `MAX_TRASH_LEN` (100).
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the deleted todo.
+///
+/// # Returns
+///
+/// A `Result<Todo, TodoError>`.
+/// On success: it returns the restored todo.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If no todo with this ID was deleted by the caller, or it was already
+///   evicted from the trash.
+/// * `IdTaken(id)`: If a stored todo has the ID by now, the deleted todo stays in the trash.
+/// * `LimitReached(max_todos)`: If the canister already stores `max_todos` todos.
+#[update(name = "undo_delete")]
+fn undo_delete(id: u64) -> Result<Todo, TodoError> {
+    let owner = caller();
+    ensure_capacity(1)?;
+    let todo = TRASH.with(|trash| {
+        let mut trash = trash.borrow_mut();
+        match trash.get(&id) {
+            Some(trashed) if trashed.todo.owner == owner => {
+                if TODOMAP.with(|todomap| todomap.borrow().contains_key(&id)) {
+                    return Err(TodoError::IdTaken(id));
+                }
+                trash.remove(&id);
+                Ok(trashed.todo)
+            }
+            _ => Err(TodoError::NotFound(id)),
+        }
+    })?;
+    TODOMAP.with(|todomap| todomap.borrow_mut().insert(id, todo.clone()));
+    FREE_IDS.with(|free_ids| free_ids.borrow_mut().remove(&id));
+    record_event(Event::Added { id, at: now() });
+    Ok(todo)
+}
+
+/// Returns the latest entries of the event log.
+///
+/// The log covers the todos of all principals and keeps the last `MAX_EVENTS` (1000) entries.
+///
+/// # Parameters
+///
+/// * `limit` (u16): The maximum number of entries to return.
+///
+/// # Returns
+///
+/// The latest `limit` events, oldest first.
+#[query(name = "recent_events")]
+fn recent_events(limit: u16) -> Vec<Event> {
+    EVENTS.with(|events| {
+        let events = events.borrow();
+        let skip = events.len().saturating_sub(limit as u64);
+        events
+            .iter()
+            .skip(skip as usize)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+/// Returns the number of todos owned by the caller.
+///
+/// Together with the page size of `read_all` this lets a frontend compute the number of pages.
+#[query(name = "count")]
+fn count() -> u64 {
+    let owner = caller();
+    TODOMAP.with(|todomap| todomap.borrow().owned_ids(owner, 0).count() as u64)
+}
+
+/// Whether the caller owns no todos at all, e.g. to render an empty-state screen.
+///
+/// Like `count() == 0`, but stops at the first todo of the caller.
+#[query(name = "is_empty")]
+fn is_empty() -> bool {
+    let owner = caller();
+    TODOMAP.with(|todomap| todomap.borrow().owned_ids(owner, 0).next().is_none())
+}
+
+/// Deletes every todo of every principal at once. Only the admin may call it.
+///
+/// `GEN_ID` is NOT reset, so IDs handed out after clearing never repeat the IDs of
+/// deleted todos. The trash and the freed IDs are emptied as well, so nothing deleted
+/// before can be undeleted. The cleared todos are not recorded in the event log one by one.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the number of todos that were removed.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[update(name = "clear_all")]
+fn clear_all() -> Result<u64, TodoError> {
+    require_admin()?;
+    Ok(TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let removed = todomap.len();
+        todomap.clear();
+        forget_deleted_todos();
+        removed
+    }))
+}
+
+/// Empties the trash and the freed IDs, for when all todos were replaced or wiped.
+fn forget_deleted_todos() {
+    FREE_IDS.with(|free_ids| {
+        *free_ids.borrow_mut() =
+            StableBTreeMap::new(MEMORY_MANAGER.with(|m| m.borrow().get(FREE_IDS_MEMORY_ID)));
+    });
+    TRASH.with(|trash| {
+        *trash.borrow_mut() =
+            StableBTreeMap::new(MEMORY_MANAGER.with(|m| m.borrow().get(TRASH_MEMORY_ID)));
+    });
+}
+
+/// Returns every stored todo of every principal, for backups and migrations between canisters.
+///
+/// Unlike `export_json` this covers all principals and keeps the candid types, so the result
+/// can be passed to `restore` as is. Only the admin may call it.
+///
+/// # Returns
+///
+/// A `Result<Vec<Todo>, TodoError>`.
+/// On success: It returns all todos ordered by ID.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::Unauthorized`
+/// If the caller is not the admin principal.
+#[query(name = "snapshot")]
+fn snapshot() -> Result<Vec<Todo>, TodoError> {
+    require_admin()?;
+    Ok(TODOMAP.with(|todomap| todomap.borrow().iter().map(|(_, todo)| todo).collect()))
+}
+
+/// Replaces all stored todos with `todos`, usually the result of `snapshot`.
+///
+/// The todos keep their IDs, owners and all other fields. `GEN_ID` is moved past the largest
+/// restored ID, so new todos never collide with restored ones. The replacement is atomic and
+/// not recorded in the event log one by one. The trash is emptied, so `undo_delete` can't
+/// bring back todos from before the restore. Only the admin may call it.
+///
+/// Every todo is checked before anything is replaced: titles, descriptions and colors like
+/// in `import_json`, tags must be non-empty, normalized and unique, and a `parent_id` must
+/// name another restored todo of the same owner without forming a cycle.
+///
+/// # Parameters
+///
+/// * `todos` (Vec<Todo>): The new content of the store. For duplicate IDs the last one wins.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the number of todos stored after the restore.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `Unauthorized`: If the caller is not the admin principal.
+/// * `LimitReached(max_todos)`: If `todos` holds more than `max_todos` todos.
+/// * `InvalidInput(message)`: For the first todo that fails the checks above, nothing is
+///   replaced then.
+#[update(name = "restore")]
+fn restore(todos: Vec<Todo>) -> Result<u64, TodoError> {
+    require_admin()?;
+    let max_todos = config().max_todos;
+    if todos.len() as u64 > max_todos {
+        return Err(TodoError::LimitReached(max_todos));
+    }
+    validate_restored(&todos)?;
+
+    let max_id = todos.iter().map(|todo| todo.id).max().unwrap_or(0);
+    GEN_ID.with(|tid| {
+        let mut tid = tid.borrow_mut();
+        if *tid.get() < max_id {
+            tid.set(max_id)
+                .expect("Failed to persist the todo ID counter");
+        }
+    });
+    Ok(TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        todomap.clear();
+        // freed and trashed IDs may be taken by the restored todos
+        forget_deleted_todos();
+        for todo in todos {
+            todomap.insert(todo.id, todo);
+        }
+        todomap.len()
+    }))
+}
+
+/// Checks the todos passed to `restore`, see there for the rules.
+fn validate_restored(todos: &[Todo]) -> Result<(), TodoError> {
+    let invalid =
+        |id: u64, problem: String| TodoError::InvalidInput(format!("Todo {} {}", id, problem));
+    // for duplicate IDs the last todo wins, like when they are stored
+    let stored: HashMap<u64, &Todo> = todos.iter().map(|todo| (todo.id, todo)).collect();
+    for todo in todos {
+        let id = todo.id;
+        if validate_title(&todo.title).is_err() {
+            return Err(invalid(id, "has an empty or too long title".to_owned()));
+        }
+        if validate_description(&todo.description).is_err() {
+            return Err(invalid(id, "has a too long description".to_owned()));
+        }
+        if let Some(color) = &todo.color {
+            if validate_color(color).is_err() {
+                return Err(invalid(id, format!("has the invalid color {:?}", color)));
+            }
+        }
+        let mut tags = HashSet::new();
+        for tag in &todo.tags {
+            if tag.is_empty() || normalize_tag(tag) != *tag {
+                return Err(invalid(id, format!("has the unnormalized tag {:?}", tag)));
+            }
+            if !tags.insert(tag) {
+                return Err(invalid(id, format!("has the tag {:?} twice", tag)));
+            }
+        }
+        // following the parents must end at a root within as many steps as there are todos
+        let mut current = todo;
+        for _ in 0..=stored.len() {
+            let Some(parent_id) = current.parent_id else {
+                break;
+            };
+            match stored.get(&parent_id) {
+                Some(parent) if parent.owner == current.owner => current = parent,
+                _ => {
+                    return Err(invalid(
+                        current.id,
+                        format!(
+                            "has the parent {} which is not a restored todo of its owner",
+                            parent_id
+                        ),
+                    ))
+                }
+            }
+        }
+        if current.parent_id.is_some() {
+            return Err(invalid(id, "is its own ancestor".to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// Summarizes the caller's todos by status.
+///
+/// All numbers are computed in a single pass over the caller's todos.
+///
+/// # Returns
+///
+/// A `Stats` record with the total number of todos and how many of them are done,
+/// pending and overdue, where overdue means the same as in `overdue`.
+#[query(name = "stats")]
+fn stats() -> Stats {
+    let owner = caller();
+    let now = now();
+    TODOMAP.with(|todomap| {
+        let mut stats = Stats::default();
+        for todo in owned_todos(&todomap.borrow(), owner) {
+            stats.total += 1;
+            if todo.done {
+                stats.done += 1;
+            } else {
+                stats.pending += 1;
+                if is_overdue(&todo, now) {
+                    stats.overdue += 1;
+                }
+            }
+        }
+        stats
+    })
+}
+
+/// Measures the title and description of a todo.
+///
+/// Both texts are counted separately and the numbers added up, so the title always
+/// counts as its own line. An empty description adds nothing.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be measured.
+///
+/// # Returns
+///
+/// A `Result<ContentStats, TodoError>`.
+/// On success: It returns the number of characters, words and lines.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[query(name = "content_stats")]
+fn content_stats(id: u64) -> Result<ContentStats, TodoError> {
+    let todo = read_todo(id)?;
+    let mut stats = ContentStats::default();
+    for text in [&todo.title, &todo.description] {
+        stats.chars += text.chars().count() as u64;
+        stats.words += text.split_whitespace().count() as u64;
+        stats.lines += text.lines().count() as u64;
+    }
+    Ok(stats)
+}
+
+/// Whether `todo` is not done and its due date lies before `now`.
+fn is_overdue(todo: &Todo, now: u64) -> bool {
+    !todo.done && todo.due_at.is_some_and(|due_at| due_at < now)
+}
+
+/// Lists the caller's todos that are not done and whose due date has passed.
+///
+/// Todos without a due date are never overdue.
+///
+/// # Returns
+///
+/// The overdue todos ordered by ID, or an empty array if nothing is overdue.
+#[query(name = "overdue")]
+fn overdue() -> Vec<Todo> {
+    let now = now();
+    TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| is_overdue(todo, now))
+            .collect()
+    })
+}
+
+/// Retrieves a paginated list of the caller's overdue todos, the longest overdue first.
+///
+/// Todos are overdue like in `overdue`. They are sorted by `due_at`, todos with the same
+/// due date by ID. Pages hold `DEFAULT_PAGE_SIZE` (10) todos.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A `Page` like `read_all`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If no todos are found on the requested page, also if nothing is overdue.
+#[query(name = "overdue_paged")]
+fn overdue_paged(page: u16) -> Result<Page, TodoError> {
+    let now = now();
+    let mut overdue: Vec<Todo> = TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| is_overdue(todo, now))
+            .collect()
+    });
+    overdue.sort_by_key(|todo| (todo.due_at, todo.id));
+    build_page(&overdue, page, DEFAULT_PAGE_SIZE)
+}
+
+/// Lists the caller's todos that are not done and due within the next `window_nanos`.
+///
+/// Todos that are already overdue are left out, see `overdue` for those.
+///
+/// # Parameters
+///
+/// * `window_nanos` (u64): How far ahead to look, in nanoseconds.
+///
+/// # Returns
+///
+/// The todos with a `due_at` between now and now plus `window_nanos`, both inclusive,
+/// the earliest due first and todos with the same due date by ID.
+#[query(name = "due_within")]
+fn due_within(window_nanos: u64) -> Vec<Todo> {
+    let now = now();
+    let until = now.saturating_add(window_nanos);
+    let mut due: Vec<Todo> = TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| {
+                !todo.done
+                    && todo
+                        .due_at
+                        .is_some_and(|due_at| (now..=until).contains(&due_at))
+            })
+            .collect()
+    });
+    due.sort_by_key(|todo| (todo.due_at, todo.id));
+    due
+}
+
+/// Lists the caller's todos that were completed between `start` and `end`, both inclusive.
+///
+/// # Parameters
+///
+/// * `start` (u64): The earliest completion time, in nanoseconds since the UNIX epoch.
+/// * `end` (u64): The latest completion time, in nanoseconds since the UNIX epoch.
+///
+/// # Returns
+///
+/// The done todos with a `completed_at` in the range, ordered by ID. Empty if `start` is
+/// greater than `end`.
+#[query(name = "completed_between")]
+fn completed_between(start: u64, end: u64) -> Vec<Todo> {
+    TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| {
+                todo.completed_at
+                    .is_some_and(|completed_at| (start..=end).contains(&completed_at))
+            })
+            .collect()
+    })
+}
+
+/// Lists the caller's todos carrying `tag`.
+///
+/// # Parameters
+///
+/// * `tag` (String): The tag to filter by. It is normalized like in `add_tag`.
+///
+/// # Returns
+///
+/// The tagged todos ordered by ID, or an empty array if none carry the tag.
+#[query(name = "by_tag")]
+fn by_tag(tag: String) -> Vec<Todo> {
+    let tag = normalize_tag(&tag);
+    TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| todo.tags.contains(&tag))
+            .collect()
+    })
+}
+
+/// Lists the distinct tags of the caller's todos, for when `tag_counts` would get too big.
+///
+/// Pages hold `DEFAULT_PAGE_SIZE` (10) tags.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A `Result<(Vec<String>, Option<u16>), TodoError>`.
+/// On success: It returns the tags on the requested page in alphabetical order and the
+///             next page number, `None` on the last page.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If there are no tags on the requested page.
+#[query(name = "list_tags")]
+fn list_tags(page: u16) -> Result<(Vec<String>, Option<u16>), TodoError> {
+    let tags: BTreeSet<String> = TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .flat_map(|todo| todo.tags)
+            .collect()
+    });
+    let tags: Vec<String> = tags.into_iter().collect();
+    let (items, next_page) = paginate(&tags, page, DEFAULT_PAGE_SIZE);
+    if items.is_empty() {
+        return Err(TodoError::InvalidPage(page.max(1)));
+    }
+    Ok((items, next_page))
+}
+
+/// Counts how many of the caller's todos carry each tag, e.g. for a sidebar of tags.
+///
+/// # Returns
+///
+/// One `(tag, count)` pair per distinct tag, the most used tags first and tags with the
+/// same count ordered by name. Empty if none of the todos are tagged.
+#[query(name = "tag_counts")]
+fn tag_counts() -> Vec<(String, u64)> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    TODOMAP.with(|todomap| {
+        for todo in owned_todos(&todomap.borrow(), caller()) {
+            for tag in todo.tags {
+                *counts.entry(tag).or_default() += 1;
+            }
+        }
+    });
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|(a_tag, a_count), (b_tag, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_tag.cmp(b_tag))
+    });
+    counts
+}
+
+/// Lowercases `text` and strips diacritics, so `Café` and `cafe` compare equal.
+///
+/// The text is decomposed (NFD) and the combining marks are dropped.
+fn fold_for_search(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Finds the caller's todos whose title is exactly `title`.
+///
+/// `title` is normalized like in `add` first, otherwise the comparison is exact, including
+/// case and diacritics. See `search` for a fuzzy lookup.
+///
+/// # Parameters
+///
+/// * `title` (String): The title to look for.
+///
+/// # Returns
+///
+/// The IDs of the matching todos in ascending order, or an empty array if nothing matches.
+#[query(name = "find_exact")]
+fn find_exact(title: String) -> Vec<u64> {
+    let Ok(title) = validate_title(&title) else {
+        return Vec::new();
+    };
+    TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| todo.title == title)
+            .map(|todo| todo.id)
+            .collect()
+    })
+}
+
+/// Finds the caller's todos whose title contains `term`, ignoring case and diacritics.
+///
+/// Only the comparison is folded, titles are returned as stored. Shared todos are searched
+/// like the caller's own.
+///
+/// # Parameters
+///
+/// * `term` (String): The text to look for. An empty term matches nothing, so a
+///                    forgotten search box never dumps every todo.
+///
+/// # Returns
+///
+/// The matching todos ordered by ID, or an empty array if nothing matches.
+#[query(name = "search")]
+fn search(term: String) -> Vec<Todo> {
+    if term.is_empty() {
+        return Vec::new();
+    }
+    let term = fold_for_search(&term);
+    TODOMAP.with(|todomap| {
+        visible_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| fold_for_search(&todo.title).contains(&term))
+            .collect()
+    })
+}
+
+/// Finds the caller's todos whose title starts with `prefix`, ignoring case.
+///
+/// This query function is meant for type-ahead and pages its results ten at a time like `read_all`.
+/// Shared todos are searched like the caller's own.
+///
+/// # Parameters
+///
+/// * `prefix` (String): The start of the title to look for.
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A tuple containing:
+///
+/// * `Vec<Todo>`: The matching todos on the requested page, ordered by ID.
+/// * `Option<u16>`: The next page number, `None` on the last page.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If there are no matches on the requested page.
+#[query(name = "search_prefix")]
+fn search_prefix(prefix: String, page: u16) -> Result<(Vec<Todo>, Option<u16>), TodoError> {
+    let prefix = prefix.to_lowercase();
+    let matches: Vec<Todo> = TODOMAP.with(|todomap| {
+        visible_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| todo.title.to_lowercase().starts_with(&prefix))
+            .collect()
+    });
+    let (items, next_page) = paginate(&matches, page, DEFAULT_PAGE_SIZE);
+    if items.is_empty() {
+        return Err(TodoError::InvalidPage(page.max(1)));
+    }
+    Ok((items, next_page))
+}
+
+/// Lists the todos the caller can read matching all of the given predicates, paged like `read_all`.
+///
+/// Without any predicate this returns the same todos as `read_all` with the default page
+/// size: pinned todos first, each group ordered by ID. Like there, todos other principals
+/// shared with the caller are included.
+///
+/// # Parameters
+///
+/// * `done` (Option<bool>): Only todos with this `done` flag, if present.
+/// * `tag` (Option<String>): Only todos carrying this tag, if present. It is normalized
+///                           like in `add_tag`.
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// A tuple containing:
+///
+/// * `Vec<Todo>`: The matching todos on the requested page.
+/// * `Option<u16>`: The next page number, `None` on the last page.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If there are no matches on the requested page.
+#[query(name = "filter")]
+fn filter(
+    done: Option<bool>,
+    tag: Option<String>,
+    page: u16,
+) -> Result<(Vec<Todo>, Option<u16>), TodoError> {
+    let tag = tag.map(|tag| normalize_tag(&tag));
+    let mut matches: Vec<Todo> = TODOMAP.with(|todomap| {
+        visible_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| done.is_none_or(|done| todo.done == done))
+            .filter(|todo| tag.as_ref().is_none_or(|tag| todo.tags.contains(tag)))
+            .collect()
+    });
+    matches.sort_by_key(|todo| !todo.pinned);
+    let (items, next_page) = paginate(&matches, page, resolve_page_size(0));
+    if items.is_empty() {
+        return Err(TodoError::InvalidPage(page.max(1)));
+    }
+    Ok((items, next_page))
+}
+
+/// Lists the done todos the caller can read, shared ones included, paged like `filter`.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// The done todos on the requested page and the next page number, like `filter`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If there are no done todos on the requested page.
+#[query(name = "get_done")]
+fn get_done(page: u16) -> Result<(Vec<Todo>, Option<u16>), TodoError> {
+    filter(Some(true), None, page)
+}
+
+/// Lists the todos the caller can read that are not done yet, shared ones included, paged
+/// like `filter`.
+///
+/// # Parameters
+///
+/// * `page` (u16): The requested page number (starting from 1).
+///
+/// # Returns
+///
+/// The pending todos on the requested page and the next page number, like `filter`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::InvalidPage(page)`
+/// If there are no pending todos on the requested page.
+#[query(name = "get_pending")]
+fn get_pending(page: u16) -> Result<(Vec<Todo>, Option<u16>), TodoError> {
+    filter(Some(false), None, page)
+}
+
+/// Sets the `done` flag of `todo`, stamping `completed_at` when the todo becomes done and
+/// clearing it when the todo is reopened.
+fn set_done_flag(todo: &mut Todo, done: bool) {
+    if !done {
+        todo.completed_at = None;
+    } else if !todo.done {
+        todo.completed_at = Some(now());
+    }
+    todo.done = done;
+}
+
+/// Sets the `done` flag of a todo to whatever `f` returns for its current value.
+fn set_done_with(id: u64, f: impl FnOnce(bool) -> bool) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        let done = f(todo.done);
+        set_done_flag(todo, done);
+        Ok(())
+    })
+}
+
+/// Changes the priority of a todo.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `priority` (Priority): The new priority.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "set_priority")]
+fn set_priority(id: u64, priority: Priority) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        todo.priority = priority;
+        Ok(())
+    })
+}
+
+/// Changes the priority of several todos at once, e.g. when triaging.
+///
+/// Unknown IDs, and IDs of todos owned by other principals, are skipped. Todos that
+/// already have `priority` are left untouched and their version doesn't change.
+///
+/// # Parameters
+///
+/// * `ids` (Vec<u64>): The unique identifiers of the todos.
+/// * `priority` (Priority): The new priority.
+///
+/// # Returns
+///
+/// The number of todos whose priority changed.
+#[update(name = "set_priority_many")]
+fn set_priority_many(ids: Vec<u64>, priority: Priority) -> u64 {
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let mut count = 0;
+        for id in ids {
+            if let Some(mut todo) = get_owned(&todomap, id, owner) {
+                if todo.priority != priority {
+                    todo.priority = priority;
+                    touch(&mut todo);
+                    todomap.insert(id, todo);
+                    count += 1;
+                }
+            }
+        }
+        count
+    })
+}
+
+/// Changes several fields of a todo in one call, leaving the others unchanged.
+///
+/// The new title is validated like in `update` before anything is changed. The todo
+/// counts as changed once, even if several fields are patched.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `patch` (TodoPatch): The fields to change.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `EmptyContent`: If the new title is empty or only consists of whitespace.
+/// * `TooLong(MAX_TODO_LEN)`: If the new title is longer than `MAX_TODO_LEN` (1000) characters.
+#[update(name = "patch")]
+fn patch_todo(id: u64, patch: TodoPatch) -> Result<(), TodoError> {
+    let title = patch.title.as_deref().map(validate_title).transpose()?;
+    with_todo_mut(id, |todo| {
+        if let Some(title) = title {
+            todo.title = title;
+        }
+        if let Some(done) = patch.done {
+            set_done_flag(todo, done);
+        }
+        if let Some(priority) = patch.priority {
+            todo.priority = priority;
+        }
+        if let Some(due_at) = patch.due_at {
+            todo.due_at = due_at;
+        }
+        Ok(())
+    })
+}
+
+/// Sets or clears the due date of a todo.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `due_at` (Option<u64>): The deadline in nanoseconds since the UNIX epoch, or `None`
+///                           to remove it.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "set_due")]
+fn set_due(id: u64, due_at: Option<u64>) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        todo.due_at = due_at;
+        Ok(())
+    })
+}
+
+/// Pushes the due date of a todo back by a relative duration.
+///
+/// A todo without a due date is treated as due now, so it ends up due in `by_nanos`.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `by_nanos` (u64): How far to push the due date back, in nanoseconds.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the new due date in nanoseconds since the UNIX epoch.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "snooze")]
+fn snooze(id: u64, by_nanos: u64) -> Result<u64, TodoError> {
+    with_todo_mut(id, |todo| {
+        let due_at = todo.due_at.unwrap_or_else(now).saturating_add(by_nanos);
+        todo.due_at = Some(due_at);
+        Ok(due_at)
+    })
+}
+
+/// Makes a todo recurring, or stops it from recurring.
+///
+/// See `mark_done` for what happens when a recurring todo is completed.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `recurrence` (Option<u64>): The interval in nanoseconds, or `None` to stop recurring.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "set_recurrence")]
+fn set_recurrence(id: u64, recurrence: Option<u64>) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        todo.recurrence = recurrence;
+        Ok(())
+    })
+}
+
+/// Trims and lowercases a tag so `Work` and ` work` end up as the same tag.
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Applies `f` to the tags of one of the caller's todos and stores the result.
+fn edit_tags(id: u64, f: impl FnOnce(&mut Vec<String>)) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        f(&mut todo.tags);
+        Ok(())
+    })
+}
+
+/// Adds a tag to a todo.
+///
+/// Tags are trimmed and lowercased. Adding a tag the todo already carries does nothing.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `tag` (String): The tag to add.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::EmptyContent`
+/// If the tag is empty or only whitespace.
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "add_tag")]
+fn add_tag(id: u64, tag: String) -> Result<(), TodoError> {
+    let tag = normalize_tag(&tag);
+    if tag.is_empty() {
+        return Err(TodoError::EmptyContent);
+    }
+    edit_tags(id, |tags| {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    })
+}
+
+/// Adds a tag to several todos at once, e.g. to a multi-selection.
+///
+/// The tag is normalized like in `add_tag`. Unknown IDs, IDs of todos owned by other
+/// principals and todos that already carry the tag are skipped, the latter keep their version.
+///
+/// # Parameters
+///
+/// * `ids` (Vec<u64>): The unique identifiers of the todos.
+/// * `tag` (String): The tag to add.
+///
+/// # Returns
+///
+/// The number of todos the tag was added to, `0` if the tag is empty or only whitespace.
+#[update(name = "add_tag_many")]
+fn add_tag_many(ids: Vec<u64>, tag: String) -> u64 {
+    let tag = normalize_tag(&tag);
+    if tag.is_empty() {
+        return 0;
+    }
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let mut count = 0;
+        for id in ids {
+            if let Some(mut todo) = get_owned(&todomap, id, owner) {
+                if !todo.tags.contains(&tag) {
+                    todo.tags.push(tag.clone());
+                    touch(&mut todo);
+                    todomap.insert(id, todo);
+                    count += 1;
+                }
+            }
+        }
+        count
+    })
+}
+
+/// Removes a tag from a todo.
+///
+/// The tag is normalized like in `add_tag`. Removing a tag the todo doesn't carry does nothing.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `tag` (String): The tag to remove.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "remove_tag")]
+fn remove_tag(id: u64, tag: String) -> Result<(), TodoError> {
+    let tag = normalize_tag(&tag);
+    edit_tags(id, |tags| tags.retain(|t| *t != tag))
+}
+
+/// Renames a tag on all of the caller's todos.
+///
+/// Both tags are normalized like in `add_tag`. The new tag takes the place of the old one,
+/// todos that already carry the new tag just lose the old one, so no tag appears twice.
+///
+/// # Parameters
+///
+/// * `old` (String): The tag to replace.
+/// * `new` (String): The tag to replace it with.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: it returns the number of todos that carried `old`, `0` if `old` and `new`
+///             are the same tag.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::EmptyContent`
+/// If the new tag is empty or only whitespace.
+#[update(name = "rename_tag")]
+fn rename_tag(old: String, new: String) -> Result<u64, TodoError> {
+    let old = normalize_tag(&old);
+    let new = normalize_tag(&new);
+    if new.is_empty() {
+        return Err(TodoError::EmptyContent);
+    }
+    if old == new {
+        return Ok(0);
+    }
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let tagged: Vec<Todo> = owned_todos(&todomap, caller())
+            .into_iter()
+            .filter(|todo| todo.tags.contains(&old))
+            .collect();
+        let count = tagged.len() as u64;
+        for mut todo in tagged {
+            if todo.tags.contains(&new) {
+                todo.tags.retain(|tag| *tag != old);
+            } else {
+                for tag in todo.tags.iter_mut().filter(|tag| **tag == old) {
+                    tag.clone_from(&new);
+                }
+            }
+            touch(&mut todo);
+            todomap.insert(todo.id, todo);
+        }
+        Ok(count)
+    })
+}
+
+/// Marks a todo as done.
+///
+/// Completing a recurring todo creates its next instance: a copy that is not done and due
+/// one `recurrence` interval after the completed one, or after now if it had no due date.
+/// The recurrence moves over to the new instance, so completing the old one again doesn't
+/// create another copy. Only `mark_done` does this, `toggle_done` and `mark_all_done` don't.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be completed.
+///
+/// # Returns
+///
+/// A `Result<Option<u64>, TodoError>`.
+/// On success: it returns the ID of the next instance for a recurring todo and `None`
+/// otherwise, also if the todo was already done.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `LimitReached(max_todos)`: If the todo is recurring and the canister already stores
+///   `max_todos` todos. The todo is not marked as done then.
+/// * `RateLimited`: If the todo is recurring and the next instance would exceed the
+///   caller's `max_adds_per_minute`, like in `add`. The todo is not marked as done then.
+#[update(name = "mark_done")]
+fn mark_done(id: u64) -> Result<Option<u64>, TodoError> {
+    let owner = caller();
+    let mut todo = TODOMAP
+        .with(|todomap| get_owned(&todomap.borrow(), id, owner))
+        .ok_or(TodoError::NotFound(id))?;
+    let Some(interval) = todo.recurrence else {
+        return set_done_with(id, |_| true).map(|()| None);
+    };
+
+    ensure_capacity(1)?;
+    check_rate_limit(owner, 1)?;
+    let mut next = new_todo(todo.title.clone(), todo.description.clone(), owner);
+    next.priority = todo.priority;
+    next.tags = todo.tags.clone();
+    next.parent_id = todo.parent_id;
+    next.recurrence = Some(interval);
+    next.due_at = Some(todo.due_at.unwrap_or_else(now).saturating_add(interval));
+
+    set_done_flag(&mut todo, true);
+    todo.recurrence = None;
+    touch(&mut todo);
+    let next_id = TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        todomap.insert(id, todo);
+        store_new_todo(&mut todomap, next)
+    });
+    Ok(Some(next_id))
+}
+
+/// Marks a todo as not done.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be reopened.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo was not done.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "mark_undone")]
+fn mark_undone(id: u64) -> Result<(), TodoError> {
+    set_done_with(id, |_| false)
+}
+
+/// Flips the `done` flag of a todo.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to be toggled.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "toggle_done")]
+fn toggle_done(id: u64) -> Result<(), TodoError> {
+    set_done_with(id, |done| !done)
 }
 
-/// Creates a new todo and returns its unique ID.
+/// Sets the `done` flag of all of the caller's todos and returns how many of them changed.
+fn set_all_done(done: bool) -> u64 {
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let changed: Vec<Todo> = owned_todos(&todomap, owner)
+            .into_iter()
+            .filter(|todo| todo.done != done)
+            .collect();
+        let count = changed.len() as u64;
+        for mut todo in changed {
+            set_done_flag(&mut todo, done);
+            touch(&mut todo);
+            todomap.insert(todo.id, todo);
+        }
+        count
+    })
+}
+
+/// Marks all of the caller's todos as done.
 ///
-/// This update function adds a new todo with the provided content to the internal storage.
-/// It assigns a unique identifier to the new todo and returns it.
+/// # Returns
+///
+/// The number of todos that were not done before. Todos that already were done are left
+/// untouched and their version doesn't change.
+#[update(name = "mark_all_done")]
+fn mark_all_done() -> u64 {
+    set_all_done(true)
+}
+
+/// Marks all of the caller's todos as not done.
+///
+/// # Returns
+///
+/// The number of todos that were done before. Todos that already were not done are left
+/// untouched and their version doesn't change.
+#[update(name = "mark_all_undone")]
+fn mark_all_undone() -> u64 {
+    set_all_done(false)
+}
+
+/// Deletes all of the caller's done todos and moves them to the trash, like `delete`.
+///
+/// Todos that are not done stay as they are, even if they are subtasks of a purged todo.
+///
+/// # Returns
+///
+/// The number of deleted todos.
+#[update(name = "purge_done")]
+fn purge_done() -> u64 {
+    let owner = caller();
+    TODOMAP.with(|todomap| {
+        let mut todomap = todomap.borrow_mut();
+        let done: Vec<Todo> = owned_todos(&todomap, owner)
+            .into_iter()
+            .filter(|todo| todo.done)
+            .collect();
+        let count = done.len() as u64;
+        for todo in done {
+            todomap.remove(&todo.id);
+            record_event(Event::Deleted {
+                id: todo.id,
+                at: now(),
+            });
+            free_id(todo.id);
+            move_to_trash(todo);
+        }
+        count
+    })
+}
+
+/// Sets the `pinned` flag of one of the caller's todos.
+fn set_pinned(id: u64, pinned: bool) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        todo.pinned = pinned;
+        Ok(())
+    })
+}
+
+/// Pins a todo, so it is listed before the unpinned todos in `read_all`.
 ///
 /// # Parameters
 ///
-/// * `todo_str` (String): The content of the new todo.
+/// * `id` (u64): The unique identifier of the todo to pin.
 ///
 /// # Returns
 ///
-/// A `Result<u16, String>`.
-/// On success:  It returns the ID for the newly created todo.
-/// On error: It return an error string. --> none as of now
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo was already pinned.
+/// On error: it contains a `TodoError`.
 ///
-#[update(name = "add")]
-fn add_todo(todo_str: String) -> Result<u16, String> {
-    let new_tid = GEN_ID.with(|tid| {
-        let mut borrowed = tid.borrow_mut();
-        let current_id = borrowed.clone();
-        *borrowed = current_id + 1;
-        borrowed.clone()
-    });
-    TODOMAP.with(|todomap| todomap.borrow_mut().insert(new_tid, todo_str));
-    Ok(new_tid)
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "pin")]
+fn pin(id: u64) -> Result<(), TodoError> {
+    set_pinned(id, true)
 }
 
-/// Reads the content of a specific todo by its ID.
-///
-/// This query function retrieves the content of a todo identified by the provided ID.
+/// Unpins a todo.
 ///
 /// # Parameters
 ///
-/// * `id` (u16): The unique identifier of the todo to be read.
+/// * `id` (u64): The unique identifier of the todo to unpin.
 ///
 /// # Returns
 ///
-/// A `Result<String, String>`.
-/// On success: It returns the content of the todo as a string.
-/// On error: It returns an error string.
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo wasn't pinned.
+/// On error: it contains a `TodoError`.
 ///
 /// # Errors
 ///
-/// This function can return an error string (`No todo with this ID`)
+/// This function can return `TodoError::NotFound(id)`
 /// If the provided ID is invalid or the todo doesn't exist.
-#[query(name = "read")]
-fn read_todo(id: u16) -> Result<String, String> {
-    TODOMAP.with(|todomap| match todomap.borrow().get(&id) {
-        Some(todo_str) => Ok(todo_str.clone()),
-        None => Err(format!("No todo with this ID {:?}", id)),
+#[update(name = "unpin")]
+fn unpin(id: u64) -> Result<(), TodoError> {
+    set_pinned(id, false)
+}
+
+/// Sets the `starred` flag of one of the caller's todos.
+fn set_starred(id: u64, starred: bool) -> Result<(), TodoError> {
+    with_todo_mut(id, |todo| {
+        todo.starred = starred;
+        Ok(())
     })
 }
 
-/// Retrieves a paginated list of todos.
+/// Stars a todo, so it is listed by `favorites`.
 ///
-/// This query function retrieves a maximum of 10 todos per page.
+/// Unlike pinning, starring doesn't change the order of `read_all`.
 ///
 /// # Parameters
 ///
-/// * `page` (u16): The requested page number (starting from 1).
+/// * `id` (u64): The unique identifier of the todo to star.
 ///
 /// # Returns
 ///
-/// A tuple containing:
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo was already starred.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "star")]
+fn star(id: u64) -> Result<(), TodoError> {
+    set_starred(id, true)
+}
+
+/// Removes the star from a todo.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo to unstar.
+///
+/// # Returns
 ///
-/// * `Vec<String>`: An array of strings representing the todo content for the requested page.
-/// * `Option<u8>`: An optional value indicating the next page number (if applicable).
-///                  If there are no more todos beyond the current page, this will be `None`.
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo wasn't starred.
+/// On error: it contains a `TodoError`.
 ///
 /// # Errors
 ///
-/// This function can return an error string (`Invalid Page <page>`) in the following cases:
-/// * Invalid `page` number.
-/// * No todos found on the requested page.
-#[query(name = "read_all")]
-fn read_all_todos(mut page: u16) -> Result<(Vec<String>, Option<u16>), String> {
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "unstar")]
+fn unstar(id: u64) -> Result<(), TodoError> {
+    set_starred(id, false)
+}
+
+/// Attaches a small file to a todo, replacing any previous attachment.
+///
+/// Attachments are stored next to the todo and fetched with `get_attachment`, so `read`
+/// and `read_all` stay small. Deleting the todo also deletes its attachment, `undo_delete`
+/// doesn't bring it back.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `bytes` (Vec<u8>): The content of the file, at most `MAX_ATTACHMENT_BYTES` (1 MiB).
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `TooLarge(MAX_ATTACHMENT_BYTES)`: If `bytes` is larger than 1 MiB.
+#[update(name = "set_attachment")]
+fn set_attachment(id: u64, bytes: Vec<u8>) -> Result<(), TodoError> {
+    if bytes.len() > MAX_ATTACHMENT_BYTES {
+        return Err(TodoError::TooLarge(MAX_ATTACHMENT_BYTES));
+    }
+    edit_attachment(id, Some(bytes))
+}
+
+/// Removes the attachment of a todo.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`, also if the todo had no attachment.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return `TodoError::NotFound(id)`
+/// If the provided ID is invalid or the todo doesn't exist.
+#[update(name = "clear_attachment")]
+fn clear_attachment(id: u64) -> Result<(), TodoError> {
+    edit_attachment(id, None)
+}
+
+/// Stores or removes the attachment of one of the caller's todos, which counts as a change
+/// of the todo.
+fn edit_attachment(id: u64, bytes: Option<Vec<u8>>) -> Result<(), TodoError> {
+    with_todo_mut(id, |_| {
+        ATTACHMENTS.with(|attachments| {
+            let mut attachments = attachments.borrow_mut();
+            match bytes {
+                Some(bytes) => attachments.insert(id, bytes),
+                None => attachments.remove(&id),
+            }
+        });
+        Ok(())
+    })
+}
+
+/// Returns the attachment of a todo the caller can read, see `read`.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+///
+/// # Returns
+///
+/// The attached bytes, or `None` if the todo has no attachment or the caller can't read it.
+#[query(name = "get_attachment")]
+fn get_attachment(id: u64) -> Option<Vec<u8>> {
+    read_todo(id).ok()?;
+    ATTACHMENTS.with(|attachments| attachments.borrow().get(&id))
+}
+
+/// Makes sure `color` is a `#` followed by six hex digits, in either case.
+fn validate_color(color: &str) -> Result<(), TodoError> {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(()),
+        _ => Err(TodoError::InvalidColor),
+    }
+}
+
+/// Sets or clears the label color of a todo.
+///
+/// # Parameters
+///
+/// * `id` (u64): The unique identifier of the todo.
+/// * `color` (Option<String>): The color as a `#RRGGBB` hex string, or `None` to remove it.
+///
+/// # Returns
+///
+/// A `Result<(), TodoError>`.
+/// On success: it returns an empty `Ok(())`.
+/// On error: it contains a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `NotFound(id)`: If the provided ID is invalid or the todo doesn't exist.
+/// * `InvalidColor`: If `color` is not a `#RRGGBB` hex string.
+#[update(name = "set_color")]
+fn set_color(id: u64, color: Option<String>) -> Result<(), TodoError> {
+    if let Some(color) = &color {
+        validate_color(color)?;
+    }
+    with_todo_mut(id, |todo| {
+        todo.color = color;
+        Ok(())
+    })
+}
+
+/// Lists all of the caller's starred todos, ordered by ID.
+#[query(name = "favorites")]
+fn favorites() -> Vec<Todo> {
     TODOMAP.with(|todomap| {
+        owned_todos(&todomap.borrow(), caller())
+            .into_iter()
+            .filter(|todo| todo.starred)
+            .collect()
+    })
+}
+
+/// Lists the most recently changed todos the caller can read, for clients syncing their state.
+///
+/// # Parameters
+///
+/// * `limit` (u16): The maximum number of todos to return. `0` selects the default page
+///   size, values above `MAX_PAGE_SIZE` are clamped.
+///
+/// # Returns
+///
+/// The todos visible to the caller ordered by `updated_at`, newest first, and by ID
+/// (highest first) for todos changed at the same time.
+#[query(name = "recently_modified")]
+fn recently_modified(limit: u16) -> Vec<Todo> {
+    let mut todos = TODOMAP.with(|todomap| visible_todos(&todomap.borrow(), caller()));
+    todos.sort_by_key(|todo| std::cmp::Reverse((todo.updated_at, todo.id)));
+    todos.truncate(resolve_page_size(limit) as usize);
+    todos
+}
+
+/// Exports all of the caller's todos as JSON, for backups and migrations.
+///
+/// # Returns
+///
+/// A JSON array with one object per todo, ordered by ID and including all fields. Without
+/// any todos this is `[]`. The owner is written as principal text.
+#[query(name = "export_json")]
+fn export_json() -> String {
+    let owned = TODOMAP.with(|todomap| owned_todos(&todomap.borrow(), caller()));
+    serde_json::to_string(&owned).expect("Failed to serialize todos to JSON")
+}
+
+/// Exports a selection of the caller's todos as JSON, like `export_json`.
+///
+/// # Parameters
+///
+/// * `ids` (Vec<u64>): The unique identifiers of the todos to export.
+///
+/// # Returns
+///
+/// A JSON array with one object per todo, in the order of `ids`. Unknown IDs, IDs of
+/// todos owned by other principals and repeated IDs are skipped.
+#[query(name = "export_json_ids")]
+fn export_json_ids(ids: Vec<u64>) -> String {
+    let owner = caller();
+    let mut seen = HashSet::new();
+    let selected: Vec<Todo> = TODOMAP.with(|todomap| {
         let todomap = todomap.borrow();
-        let limit = 10;
-        page = std::cmp::max(page, 1);
+        ids.into_iter()
+            .filter(|id| seen.insert(*id))
+            .filter_map(|id| get_owned(&todomap, id, owner))
+            .collect()
+    });
+    serde_json::to_string(&selected).expect("Failed to serialize todos to JSON")
+}
 
-        let start_index = (page - 1) * limit;
+/// Quotes a CSV field if it contains a separator, quote or line break, doubling inner quotes.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
 
-        let todo_slice: Vec<_> = todomap
-            .values()
-            .skip(start_index as usize)
-            .take(limit as usize)
-            .cloned()
-            .collect();
+/// Exports the caller's todos as CSV, for spreadsheets.
+///
+/// # Returns
+///
+/// A header row `id,title,done,created_at` followed by one row per todo, ordered by ID.
+/// Titles are quoted as described in RFC 4180 when needed. Without any todos only the
+/// header row is returned. Every row ends with `\r\n`.
+#[query(name = "export_csv")]
+fn export_csv() -> String {
+    let owned = TODOMAP.with(|todomap| owned_todos(&todomap.borrow(), caller()));
+    let mut csv = String::from("id,title,done,created_at\r\n");
+    for todo in owned {
+        csv.push_str(&format!(
+            "{},{},{},{}\r\n",
+            todo.id,
+            csv_field(&todo.title),
+            todo.done,
+            todo.created_at
+        ));
+    }
+    csv
+}
+
+/// One entry of the array passed to `import_json`.
+///
+/// Only `title` is required, so both `export_json` output and hand-written files can be
+/// imported. `id`, `owner`, `shared_with`, `order` and `updated_at` are ignored if present.
+#[derive(Deserialize)]
+struct ImportedTodo {
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    due_at: Option<u64>,
+    #[serde(default)]
+    recurrence: Option<u64>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    starred: bool,
+    #[serde(default)]
+    color: Option<String>,
+    /// Kept if present, so a restored backup remembers when its todos were created.
+    #[serde(default)]
+    created_at: Option<u64>,
+    /// Kept for done todos if present, otherwise done todos count as completed at import time.
+    #[serde(default)]
+    completed_at: Option<u64>,
+}
+
+/// One entry of the array passed to `import_preserving_ids`, an `ImportedTodo` plus its ID.
+#[derive(Deserialize)]
+struct ImportedTodoWithId {
+    id: u64,
+    #[serde(flatten)]
+    todo: ImportedTodo,
+}
+
+/// Validates an imported entry like `add` does and returns the cleaned title and description.
+fn validate_imported(entry: ImportedTodo) -> Result<(String, String, ImportedTodo), TodoError> {
+    let title = validate_title(&entry.title)?;
+    let description = validate_description(&entry.description)?;
+    if let Some(color) = &entry.color {
+        validate_color(color)?;
+    }
+    Ok((title, description, entry))
+}
 
-        if todo_slice.is_empty() {
-            return Err(format!("Invalid Page {}", page).to_string());
+/// Copies the fields of a validated imported entry onto `todo`.
+fn apply_imported(todo: &mut Todo, entry: ImportedTodo) {
+    set_done_flag(todo, entry.done);
+    if entry.done && entry.completed_at.is_some() {
+        todo.completed_at = entry.completed_at;
+    }
+    todo.priority = entry.priority;
+    todo.due_at = entry.due_at;
+    todo.recurrence = entry.recurrence;
+    todo.pinned = entry.pinned;
+    todo.starred = entry.starred;
+    todo.color = entry.color;
+    for tag in entry.tags.iter().map(|tag| normalize_tag(tag)) {
+        if !tag.is_empty() && !todo.tags.contains(&tag) {
+            todo.tags.push(tag);
         }
+    }
+    if let Some(created_at) = entry.created_at {
+        todo.created_at = created_at;
+    }
+}
 
-        let next_page = if todomap.len() as u16 > start_index + limit {
-            Some(page + 1)
-        } else {
-            None
-        };
+/// Imports todos from a JSON array, like the one returned by `export_json`.
+///
+/// All imported todos get fresh IDs and belong to the caller, so importing never
+/// overwrites existing todos, not even when the same backup is imported twice.
+/// The import is atomic: every entry is validated like in `add` before anything is stored.
+///
+/// # Parameters
+///
+/// * `json` (String): A JSON array of todo objects, only `title` is required.
+///
+/// # Returns
+///
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the number of imported todos.
+/// On error: It returns a `TodoError`.
+///
+/// # Errors
+///
+/// This function can return a `TodoError` in the following cases:
+/// * `InvalidJson(message)`: If `json` is malformed or not an array of todo objects.
+/// * `InvalidEntry { index, error }`: For the first entry that fails validation, where
+///   `error` is the error `add` would have returned.
+/// * `LimitReached(max_todos)`: If the whole import doesn't fit below `max_todos`.
+/// * `RateLimited`: If the whole import doesn't fit into the caller's `max_adds_per_minute`.
+#[update(name = "import_json")]
+fn import_json(json: String) -> Result<u64, TodoError> {
+    let imported: Vec<ImportedTodo> =
+        serde_json::from_str(&json).map_err(|error| TodoError::InvalidJson(error.to_string()))?;
+    let validated = imported
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            validate_imported(entry).map_err(|error| TodoError::InvalidEntry {
+                index: index as u64,
+                error: Box::new(error),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-        Ok((todo_slice, next_page))
-    })
+    let owner = caller();
+    let count = validated.len() as u64;
+    ensure_capacity(count)?;
+    check_rate_limit(owner, count)?;
+    for (title, description, entry) in validated {
+        let mut todo = new_todo(title, description, owner);
+        apply_imported(&mut todo, entry);
+        TODOMAP.with(|todomap| store_new_todo(&mut todomap.borrow_mut(), todo));
+    }
+    Ok(count)
 }
 
-/// Updates the content of an existing todo.
+/// Imports todos from a JSON array like `import_json`, but keeps the IDs of the entries.
 ///
-/// This update function modifies the content of a todo identified by the provided ID with the new content.
+/// Every entry needs an `id` next to its `title`. An ID that is already taken by one of the
+/// caller's todos is an error, unless `overwrite` is set, in which case that todo is replaced
+/// and keeps only its position in the list. The ID counter is moved past the largest imported
+/// ID, so new todos never collide with imported ones. The import is atomic like `import_json`.
 ///
 /// # Parameters
 ///
-/// * `id` (u16): The unique identifier of the todo to be updated.
-/// * `new_todo_str` (String): The new content for the todo.
+/// * `json` (String): A JSON array of todo objects, only `id` and `title` are required.
+/// * `overwrite` (bool): Whether to replace the caller's todos whose IDs are imported.
 ///
 /// # Returns
 ///
-/// A `Result<(), String>`.
-/// On success: It returns an empty `Ok(())`.
-/// On error: It returns an error  string.
+/// A `Result<u64, TodoError>`.
+/// On success: It returns the number of imported todos, replaced ones included.
+/// On error: It returns a `TodoError`.
 ///
 /// # Errors
 ///
-/// This function can return an error string (`No todo with this ID: <todo_id> found. Invalid operation`)
-/// If the provided ID is invalid or the todo doesn't exist.
-#[update(name = "update")]
-fn update_todo(id: u16, new_todo_str: String) -> Result<(), String> {
-    TODOMAP.with(|todomap| {
-        let mut todomap = todomap.borrow_mut();
-        match todomap.get(&id) {
-            Some(_) => {
-                todomap.insert(id, new_todo_str);
-                Ok(())
-            }
-            None => {
-                Err(format!("No todo with this ID: {:?} found. Invalid operation", id).to_string())
-            }
+/// This function can return a `TodoError` in the following cases:
+/// * `InvalidJson(message)`: If `json` is malformed or not an array of todo objects.
+/// * `InvalidEntry { index, error }`: For the first entry that fails validation, where
+///   `error` is `IdTaken(id)` if an earlier entry, a todo of another principal or, without
+///   `overwrite`, one of the caller's todos has the same ID, and the error `add` would have
+///   returned otherwise.
+/// * `LimitReached(max_todos)`: If the new todos don't fit below `max_todos`.
+/// * `RateLimited`: If the new todos don't fit into the caller's `max_adds_per_minute`,
+///   replaced todos don't count.
+#[update(name = "import_preserving_ids")]
+fn import_preserving_ids(json: String, overwrite: bool) -> Result<u64, TodoError> {
+    let imported: Vec<ImportedTodoWithId> =
+        serde_json::from_str(&json).map_err(|error| TodoError::InvalidJson(error.to_string()))?;
+    let owner = caller();
+    let mut seen = HashSet::new();
+    let validated = TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        imported
+            .into_iter()
+            .enumerate()
+            .map(|(index, ImportedTodoWithId { id, todo: entry })| {
+                let existing = todomap.get(&id);
+                let taken = !seen.insert(id)
+                    || existing
+                        .as_ref()
+                        .is_some_and(|todo| todo.owner != owner || !overwrite);
+                if taken {
+                    Err(TodoError::IdTaken(id))
+                } else {
+                    validate_imported(entry).map(|validated| (id, existing, validated))
+                }
+                .map_err(|error| TodoError::InvalidEntry {
+                    index: index as u64,
+                    error: Box::new(error),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    let count = validated.len() as u64;
+    let added = validated
+        .iter()
+        .filter(|(_, existing, _)| existing.is_none())
+        .count() as u64;
+    ensure_capacity(added)?;
+    check_rate_limit(owner, added)?;
+    let max_id = validated.iter().map(|(id, _, _)| *id).max().unwrap_or(0);
+    GEN_ID.with(|tid| {
+        let mut tid = tid.borrow_mut();
+        if *tid.get() < max_id {
+            tid.set(max_id)
+                .expect("Failed to persist the todo ID counter");
         }
-    })
+    });
+    for (id, existing, (title, description, entry)) in validated {
+        let mut todo = todo_with_id(id, title, description, owner);
+        apply_imported(&mut todo, entry);
+        TODOMAP.with(|todomap| {
+            let mut todomap = todomap.borrow_mut();
+            match existing {
+                Some(existing) => {
+                    todo.order = existing.order;
+                    todo.version = existing.version;
+                    touch(&mut todo);
+                    todomap.insert(id, todo);
+                }
+                None => {
+                    // the ID may have been freed, so it must not be handed out or undeleted again
+                    FREE_IDS.with(|free_ids| free_ids.borrow_mut().remove(&id));
+                    TRASH.with(|trash| trash.borrow_mut().remove(&id));
+                    record_event(Event::Added { id, at: now() });
+                    store_new_todo(&mut todomap, todo);
+                }
+            }
+        });
+    }
+    Ok(count)
 }
 
-/// Deletes a todo by its ID.
+/// Returns the cycles balance of the canister.
+///
+/// Dashboards can poll this to warn before the canister runs out of cycles and freezes.
 ///
-/// This update function removes a todo identified by the provided ID from the internal storage.
+/// # Returns
+///
+/// The current balance in cycles.
+#[query(name = "cycles_balance")]
+fn cycles_balance() -> u64 {
+    ic_cdk::api::canister_balance()
+}
+
+/// Reports how much memory the canister uses.
+///
+/// # Returns
+///
+/// A `MemStats` record with the heap and stable memory sizes in bytes and the number of
+/// todos of all principals.
+#[query(name = "memory_stats")]
+fn memory_stats() -> MemStats {
+    MemStats {
+        heap_bytes: heap_bytes(),
+        stable_bytes: stable_bytes(),
+        todo_count: TODOMAP.with(|todomap| todomap.borrow().len()),
+    }
+}
+
+/// Renders the canister wide metrics in the Prometheus text exposition format.
+fn render_metrics() -> String {
+    let (total, done) = TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        let done = todomap.iter().filter(|(_, todo)| todo.done).count() as u64;
+        (todomap.len(), done)
+    });
+    let stable_memory_bytes = stable_bytes();
+
+    let mut metrics = String::new();
+    for (name, help, value) in [
+        (
+            "todo_total",
+            "Number of stored todos of all principals.",
+            total,
+        ),
+        ("todo_done", "Number of stored todos that are done.", done),
+        (
+            "stable_memory_bytes",
+            "Size of the stable memory in bytes.",
+            stable_memory_bytes,
+        ),
+    ] {
+        metrics.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+        ));
+    }
+    metrics
+}
+
+/// Serves HTTP requests coming in through the HTTP gateway.
+///
+/// `/metrics` returns gauges for monitoring in the Prometheus text exposition format, the
+/// numbers cover the todos of all principals. Every other path returns `404`.
 ///
 /// # Parameters
 ///
-/// * `id` (u16): The unique identifier of the todo to be deleted.
+/// * `req` (HttpRequest): The request, only its path is looked at.
 ///
 /// # Returns
 ///
-/// A `Result<(), String>`.
-/// On success: it returns an empty `Ok(())`.
-/// On error: it contains an error message string.
+/// The `HttpResponse` for the gateway.
+#[query(name = "http_request")]
+fn http_request(req: HttpRequest) -> HttpResponse {
+    let path = req.url.split('?').next().unwrap_or_default();
+    match path {
+        "/metrics" => HttpResponse {
+            status_code: 200,
+            headers: vec![(
+                "Content-Type".to_owned(),
+                "text/plain; version=0.0.4".to_owned(),
+            )],
+            body: render_metrics().into_bytes(),
+        },
+        _ => HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_owned(), "text/plain".to_owned())],
+            body: b"Not found".to_vec(),
+        },
+    }
+}
+
+/// Brings stable memory written by earlier versions into the current layout, publishes the
+/// hash of the stored todos again, since upgrades clear the certified data, and restarts the
+/// reminder timers and the daily cleanup.
 ///
-/// # Errors
+/// Each step recognizes the layout it migrates from and does nothing otherwise, so canisters
+/// upgraded from any earlier version end up in the current layout. The cleanup also runs right
+/// after the upgrade, so frequent upgrades can't postpone it.
 ///
-/// This function can return an error string (`No todo with this ID: <todo_id> found.`)
-/// If the provided ID is invalid or the todo doesn't exist.
-#[update(name = "delete")]
-fn delete_todo(id: u16) -> Result<(), String> {
-    TODOMAP.with(|todomap| match todomap.borrow_mut().remove_entry(&id) {
-        Some(_) => Ok(()),
-        None => Err(format!("No todo with this ID: {:?} found.", id)),
-    })
+/// # Panics
+///
+/// Traps if the stored state can not be decoded, which rolls the upgrade back.
+#[post_upgrade]
+fn post_upgrade() {
+    migrate_heap_todos();
+    migrate_u16_ids();
+    migrate_text_todos();
+    TODOMAP.with(|todomap| {
+        let todomap = todomap.borrow();
+        todomap.certify();
+        for (id, todo) in todomap.iter() {
+            set_reminder(id, reminder_due(&todo));
+        }
+    });
+    start_daily_cleanup();
+    set_timer(Duration::ZERO, daily_cleanup);
+}
+
+/// Moves todos saved by the upgrade hooks of the heap versions into the stable map.
+///
+/// The heap versions `stable_save`d the todos together with the ID counter before every
+/// upgrade. That candid encoding starts with the `DIDL` magic at the very beginning of stable
+/// memory, where the memory manager would find its own header otherwise. It is decoded before
+/// the memory manager is first touched, which then finds no header and starts over, so the
+/// todos and the counter end up in the layout of the first stable version, with `u16` IDs,
+/// and the later steps take it from there.
+fn migrate_heap_todos() {
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return;
+    }
+    let mut magic = [0; 4];
+    ic_cdk::api::stable::stable64_read(0, &mut magic);
+    if &magic != b"DIDL" {
+        return;
+    }
+    let (todos, gen_id): (BTreeMap<u16, String>, u16) =
+        ic_cdk::storage::stable_restore().expect("Failed to restore todos from stable memory");
+    let mut stable_todos: StableBTreeMap<u16, String, Memory> =
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TODOS_MEMORY_ID)));
+    for (id, todo) in todos {
+        stable_todos.insert(id, todo);
+    }
+    StableCell::new(
+        MEMORY_MANAGER.with(|m| m.borrow().get(GEN_ID_MEMORY_ID)),
+        gen_id,
+    )
+    .expect("Failed to persist the todo ID counter");
+}
+
+/// Widens the todo keys and the ID counter stored by versions with `u16` IDs to `u64`.
+///
+/// Those versions stored the counter as the two bytes of a `u16`, which tells them apart.
+/// Their todos are read back with the old key type and written into a fresh map. This runs
+/// before `TODOMAP` and `GEN_ID` are first used, so both open the migrated structures.
+fn migrate_u16_ids() {
+    let counter_memory = MEMORY_MANAGER.with(|m| m.borrow().get(GEN_ID_MEMORY_ID));
+    if counter_memory.size() == 0 {
+        return;
+    }
+    let counter: StableCell<Vec<u8>, Memory> = StableCell::init(counter_memory.clone(), Vec::new())
+        .expect("Failed to read the todo ID counter");
+    let Ok(old_counter) = <[u8; 2]>::try_from(counter.get().as_slice()) else {
+        return;
+    };
+
+    let todos_memory = MEMORY_MANAGER.with(|m| m.borrow().get(TODOS_MEMORY_ID));
+    let old_todos: StableBTreeMap<u16, String, Memory> = StableBTreeMap::init(todos_memory.clone());
+    let todos: Vec<(u16, String)> = old_todos.iter().collect();
+    let mut new_todos: StableBTreeMap<u64, String, Memory> = StableBTreeMap::new(todos_memory);
+    for (id, todo) in todos {
+        new_todos.insert(u64::from(id), todo);
+    }
+    StableCell::new(counter_memory, u64::from(u16::from_be_bytes(old_counter)))
+        .expect("Failed to persist the todo ID counter");
+}
+
+/// Turns the plain text values stored by versions before the `Todo` record into records.
+///
+/// Those versions stored the text as is, records are candid and start with the `DIDL` magic.
+/// A store is migrated as a whole, so looking at its first value is enough.
+fn migrate_text_todos() {
+    /// The first layout of `Todo`, which later versions still decode.
+    #[derive(CandidType)]
+    struct TextTodo {
+        id: u64,
+        content: String,
+    }
+
+    let mut stored: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(TODOS_MEMORY_ID)));
+    match stored.first_key_value() {
+        Some((_, bytes)) if !bytes.starts_with(b"DIDL") => {}
+        _ => return,
+    }
+    let texts: Vec<(u64, Vec<u8>)> = stored.iter().collect();
+    for (id, text) in texts {
+        let todo = TextTodo {
+            id,
+            content: String::from_utf8(text).expect("Failed to decode a text todo"),
+        };
+        stored.insert(id, Encode!(&todo).expect("Failed to encode todo"));
+    }
 }
 
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_keep_increasing_past_the_old_u16_boundary() {
+        let boundary = u16::MAX as u64;
+        GEN_ID.with(|tid| tid.borrow_mut().set(boundary - 1).unwrap());
+
+        let ids: Vec<u64> = (0..3)
+            .map(|i| add_todo(format!("Todo {}", i), String::new()).unwrap())
+            .collect();
+
+        assert_eq!(ids, vec![boundary, boundary + 1, boundary + 2]);
+        // no ID wrapped around, so nothing got overwritten
+        assert_eq!(TODOMAP.with(|todomap| todomap.borrow().len()), 3);
+        assert_eq!(read_todo(boundary + 1).unwrap().title, "Todo 1");
+    }
+
+    #[test]
+    fn memory_numbers_are_available_outside_a_canister() {
+        add_todo("First".to_owned(), String::new()).unwrap();
+        let stats = memory_stats();
+        assert_eq!((stats.heap_bytes, stats.stable_bytes), (0, 0));
+        assert_eq!(stats.todo_count, 1);
+        assert!(render_metrics().contains("\nstable_memory_bytes 0\n"));
+    }
+
+    #[test]
+    fn todos_stored_by_earlier_versions_still_decode() {
+        // the layout todos were first stored in, before they had a done flag
+        #[derive(CandidType)]
+        struct FirstTodo {
+            id: u64,
+            content: String,
+        }
+        let bytes = Encode!(&FirstTodo {
+            id: 7,
+            content: "Old todo".to_owned(),
+        })
+        .unwrap();
+        let todo = Todo::from_bytes(Cow::Owned(bytes));
+        assert_eq!(todo.id, 7);
+        assert_eq!(todo.title, "Old todo");
+        assert_eq!(todo.description, "");
+        assert_eq!(todo.owner, Principal::anonymous());
+        assert!(!todo.done);
+        assert_eq!(todo.completed_at, None);
+        assert_eq!((todo.created_at, todo.updated_at), (0, 0));
+        assert_eq!(todo.priority, Priority::Medium);
+        assert_eq!(todo.order, 7);
+        assert_eq!(todo.version, 0);
+
+        // todos in the trash fall back the same way
+        #[derive(CandidType)]
+        struct OwnedTodo {
+            id: u64,
+            owner: Principal,
+            content: String,
+            done: bool,
+            created_at: u64,
+            updated_at: u64,
+        }
+        #[derive(CandidType)]
+        struct FirstTrashedTodo {
+            todo: OwnedTodo,
+            deleted_at: u64,
+        }
+        let owner = Principal::from_slice(&[1]);
+        let bytes = Encode!(&FirstTrashedTodo {
+            todo: OwnedTodo {
+                id: 3,
+                owner,
+                content: "Trashed".to_owned(),
+                done: true,
+                created_at: 10,
+                updated_at: 20,
+            },
+            deleted_at: 50,
+        })
+        .unwrap();
+        let trashed = TrashedTodo::from_bytes(Cow::Owned(bytes));
+        assert_eq!(trashed.deleted_at, 50);
+        assert_eq!(trashed.todo.owner, owner);
+        assert_eq!(trashed.todo.title, "Trashed");
+        assert!(trashed.todo.done);
+        assert_eq!(trashed.todo.completed_at, Some(20));
+        assert_eq!((trashed.todo.created_at, trashed.todo.updated_at), (10, 20));
+        assert_eq!(trashed.todo.priority, Priority::Medium);
+
+        // current todos round-trip unchanged
+        let current = Todo::from_bytes(Cow::Owned(todo.to_bytes().into_owned()));
+        assert_eq!(current, todo);
+    }
+
+    #[test]
+    fn read_all_pages_match_with_and_without_gaps() {
+        for i in 1..=25 {
+            add_todo(format!("Todo {}", i), String::new()).unwrap();
+        }
+        let page_ids = |page| -> Vec<u64> {
+            let todos = read_all_todos(page, 0, None).unwrap().items;
+            todos.iter().map(|todo| todo.id).collect()
+        };
+        assert_eq!(page_ids(2), (11..=20).collect::<Vec<u64>>());
+
+        delete_todo(5).unwrap();
+        assert_eq!(page_ids(2), (12..=21).collect::<Vec<u64>>());
+        assert_eq!(page_ids(3), (22..=25).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn indexes_follow_the_todos() {
+        let other = Principal::from_slice(&[7]);
+        let owned = |owner| -> Vec<u64> {
+            TODOMAP.with(|todomap| todomap.borrow().owned_ids(owner, 0).collect())
+        };
+        let shared = |reader| -> Vec<u64> {
+            TODOMAP.with(|todomap| todomap.borrow().shared_ids(reader, 0).collect())
+        };
+        let first = add_todo("First".to_owned(), String::new()).unwrap();
+        let second = add_todo("Second".to_owned(), String::new()).unwrap();
+        assert_eq!(owned(Principal::anonymous()), vec![first, second]);
+        assert!(owned(other).is_empty());
+
+        // a new owner moves the todo to the range of that owner
+        TODOMAP.with(|todomap| {
+            let mut todomap = todomap.borrow_mut();
+            let mut todo = todomap.get(&second).unwrap();
+            todo.owner = other;
+            todomap.insert(second, todo);
+        });
+        assert_eq!(owned(Principal::anonymous()), vec![first]);
+        assert_eq!(owned(other), vec![second]);
+
+        share(first, other).unwrap();
+        assert_eq!(shared(other), vec![first]);
+        unshare(first, other).unwrap();
+        assert!(shared(other).is_empty());
+
+        share(first, other).unwrap();
+        delete_todo(first).unwrap();
+        assert!(owned(Principal::anonymous()).is_empty());
+        assert!(shared(other).is_empty());
+    }
+
+    #[test]
+    fn config_stored_by_earlier_versions_still_decodes() {
+        // the layout of the sweep, before dedup mode and the other later settings
+        #[derive(CandidType)]
+        struct SweepConfig {
+            default_page_size: u16,
+            admin: Option<Principal>,
+            done_ttl_nanos: u64,
+        }
+        let admin = Principal::from_slice(&[42]);
+        let bytes = Encode!(&SweepConfig {
+            default_page_size: 25,
+            admin: Some(admin),
+            done_ttl_nanos: 3600,
+        })
+        .unwrap();
+        let config = Config::from_bytes(Cow::Owned(bytes));
+        assert_eq!(config.default_page_size, 25);
+        assert_eq!(config.admin, Some(admin));
+        assert_eq!(config.done_ttl_nanos, 3600);
+        assert!(!config.dedup);
+        assert_eq!(config.max_todos, DEFAULT_MAX_TODOS);
+        assert_eq!(config.max_adds_per_minute, DEFAULT_MAX_ADDS_PER_MINUTE);
+        assert_eq!(config.subscriber, None);
+        assert_eq!(
+            config.collapse_whitespace,
+            Config::default().collapse_whitespace
+        );
+    }
+
+    #[test]
+    fn paginate_cuts_pages_and_hints_the_next_one() {
+        let items: Vec<u32> = (1..=25).collect();
+
+        // page 0 is read as the first page
+        assert_eq!(paginate(&items, 0, 10), paginate(&items, 1, 10));
+        assert_eq!(paginate(&items, 1, 10), ((1..=10).collect(), Some(2)));
+        // the last page is only partially filled and has no next page
+        assert_eq!(paginate(&items, 3, 10), ((21..=25).collect(), None));
+        // a full last page has no next page either
+        assert_eq!(paginate(&items, 5, 5), ((21..=25).collect(), None));
+        // beyond the end there is nothing left
+        assert_eq!(paginate(&items, 4, 10), (vec![], None));
+        assert_eq!(paginate::<u32>(&[], 1, 10), (vec![], None));
+    }
+
+    #[test]
+    fn read_all_rejects_pages_past_the_end() {
+        for i in 1..=25 {
+            add_todo(format!("Todo {}", i), String::new()).unwrap();
+        }
+        let first = read_all_todos(1, 10, None).unwrap();
+        assert_eq!(read_all_todos(0, 10, None).unwrap().items, first.items);
+        assert_eq!(read_all_todos(3, 10, None).unwrap().next_page, None);
+        // one past the last page
+        assert_eq!(
+            read_all_todos(4, 10, None).unwrap_err(),
+            TodoError::InvalidPage(4)
+        );
+        // no overflow, even when the offset does not fit a u16
+        assert_eq!(
+            read_all_todos(u16::MAX, 10, None).unwrap_err(),
+            TodoError::InvalidPage(u16::MAX)
+        );
+        let items: Vec<u16> = (0..u16::MAX).collect();
+        assert_eq!(paginate(&items, u16::MAX, 2), (vec![], None));
+        assert_eq!(paginate(&items, 2, u16::MAX), (vec![], None));
+    }
+
+    #[test]
+    fn with_todo_mut_only_stores_successful_changes() {
+        assert_eq!(
+            with_todo_mut(1, |_| Ok(())).unwrap_err(),
+            TodoError::NotFound(1)
+        );
+
+        let id = add_todo("Todo".to_owned(), String::new()).unwrap();
+        let title = with_todo_mut(id, |todo| {
+            todo.title = "Changed".to_owned();
+            Ok(todo.title.clone())
+        });
+        assert_eq!(title.unwrap(), "Changed");
+        let changed = read_todo(id).unwrap();
+        assert_eq!(changed.title, "Changed");
+        assert_eq!(changed.version, 1);
+
+        // a failing closure leaves the todo untouched
+        let failed: Result<(), TodoError> = with_todo_mut(id, |todo| {
+            todo.title = "Lost".to_owned();
+            Err(TodoError::EmptyContent)
+        });
+        assert_eq!(failed.unwrap_err(), TodoError::EmptyContent);
+        assert_eq!(read_todo(id).unwrap(), changed);
+    }
+
+    #[test]
+    fn reminders_follow_due_dates() {
+        let has_reminder = |id| REMINDERS.with(|reminders| reminders.borrow().contains_key(&id));
+        let id = add_todo("Due".to_owned(), String::new()).unwrap();
+        assert!(!has_reminder(id));
+
+        set_due(id, Some(now() + DAY_NANOS)).unwrap();
+        assert!(has_reminder(id));
+        // done todos and past due dates get no reminder
+        set_done_with(id, |_| true).unwrap();
+        assert!(!has_reminder(id));
+        set_done_with(id, |_| false).unwrap();
+        assert!(has_reminder(id));
+        set_due(id, Some(now() - DAY_NANOS)).unwrap();
+        assert!(!has_reminder(id));
+
+        set_due(id, Some(now() + DAY_NANOS)).unwrap();
+        delete_todo(id).unwrap();
+        assert!(!has_reminder(id));
+    }
+
+    #[test]
+    fn candid_interface_matches_the_did_file() {
+        // dfx serves the committed file, so a changed method signature has to show up there.
+        // After an intended change regenerate it with `npm run generate`.
+        let committed = include_str!("../todo_rust_backend.did");
+        assert_eq!(
+            __export_service().trim_end(),
+            committed.trim_end(),
+            "todo_rust_backend.did is out of sync with the exported interface"
+        );
+    }
+
+    #[test]
+    fn store_hash_follows_the_contents() {
+        let hash = || TODOMAP.with(|todomap| todomap.borrow().hash);
+        assert_eq!(hash(), [0; 32]);
+
+        let first = add_todo("First".to_owned(), String::new()).unwrap();
+        let with_first = hash();
+        let second = add_todo("Second".to_owned(), String::new()).unwrap();
+        assert_ne!(hash(), with_first);
+
+        // deleting brings the hash back, no matter in which order todos were added
+        delete_todo(second).unwrap();
+        assert_eq!(hash(), with_first);
+        delete_todo(first).unwrap();
+        assert_eq!(hash(), [0; 32]);
+
+        // the running hash always matches a fresh computation
+        add_todo("Third".to_owned(), String::new()).unwrap();
+        update_todo(3, "Changed".to_owned(), 0).unwrap();
+        let recomputed = TODOMAP.with(|todomap| {
+            let mut hash = [0; 32];
+            for (_, todo) in todomap.borrow().iter() {
+                add_hash(&mut hash, &todo_hash(&todo));
+            }
+            hash
+        });
+        assert_eq!(hash(), recomputed);
+    }
+}
@@ -1,9 +1,170 @@
 use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
-use candid::{encode_one, Principal};
+use candid::{decode_one, encode_args, encode_one, CandidType, Deserialize, Principal};
 use ic_cdk::api::management_canister::main::CanisterId;
 use pocket_ic::{PocketIc, WasmResult};
 
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+enum SortBy {
+    IdDesc,
+    TitleAsc,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct Todo {
+    id: u64,
+    owner: Principal,
+    shared_with: Vec<Principal>,
+    title: String,
+    description: String,
+    done: bool,
+    completed_at: Option<u64>,
+    priority: Priority,
+    due_at: Option<u64>,
+    recurrence: Option<u64>,
+    tags: Vec<String>,
+    order: u32,
+    pinned: bool,
+    starred: bool,
+    color: Option<String>,
+    parent_id: Option<u64>,
+    created_at: u64,
+    updated_at: u64,
+    version: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+enum TodoError {
+    NotFound(u64),
+    EmptyContent,
+    TooLong(usize),
+    InvalidPage(u16),
+    InvalidEntry { index: u64, error: Box<TodoError> },
+    Unauthorized,
+    VersionConflict { current: u64 },
+    Duplicate,
+    LimitReached(u64),
+    InvalidJson(String),
+    InvalidInput(String),
+    InvalidColor,
+    RateLimited,
+    TooLarge(usize),
+    IdTaken(u64),
+    InvalidPageSize(u16),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+enum Event {
+    Added { id: u64, at: u64 },
+    Updated { id: u64, at: u64 },
+    Deleted { id: u64, at: u64 },
+    Reminder { id: u64, at: u64 },
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct MemStats {
+    heap_bytes: u64,
+    stable_bytes: u64,
+    todo_count: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct Stats {
+    total: u64,
+    done: u64,
+    pending: u64,
+    overdue: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct ContentStats {
+    chars: u64,
+    words: u64,
+    lines: u64,
+}
+
+type TitlePage = (Vec<(u64, String)>, Option<u16>);
+
+#[derive(CandidType, Deserialize)]
+struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct InitArgs {
+    default_page_size: u16,
+    admin: Principal,
+    done_ttl_nanos: Option<u64>,
+    max_todos: Option<u64>,
+    max_adds_per_minute: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct Config {
+    default_page_size: u16,
+    admin: Option<Principal>,
+    done_ttl_nanos: u64,
+    dedup: bool,
+    max_todos: u64,
+    reuse_ids: bool,
+    max_adds_per_minute: u32,
+    subscriber: Option<Principal>,
+    collapse_whitespace: bool,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct ConfigPatch {
+    default_page_size: Option<u16>,
+    max_todos: Option<u64>,
+    dedup: Option<bool>,
+    done_ttl_nanos: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct TodoPatch {
+    title: Option<String>,
+    done: Option<bool>,
+    priority: Option<Priority>,
+    due_at: Option<Option<u64>>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct CreateTodo {
+    title: String,
+    description: String,
+    tags: Vec<String>,
+    priority: Priority,
+    due_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Page {
+    items: Vec<Todo>,
+    next_page: Option<u16>,
+    total_items: u64,
+    total_pages: u16,
+}
+
 #[test]
 fn test_todo_canister() {
     let pic = PocketIc::new();
@@ -12,42 +173,4272 @@ fn test_todo_canister() {
     pic.add_cycles(canister_id, 2_000_000_000_000);
 
     let wasm_bytes = load_todos_wasm();
-    pic.install_canister(canister_id, wasm_bytes, vec![], None);
+    pic.install_canister(
+        canister_id,
+        wasm_bytes,
+        encode_one(init_args()).unwrap(),
+        None,
+    );
     // test 'add' a new todo and check the id returned as 1.
     let res = add_new_todo(&pic, canister_id, "add");
-    assert_eq!(res, WasmResult::Reply("1".to_owned().as_bytes().to_vec()));
+    assert_eq!(res, Ok(1));
 
     // test 'read'
     let res = get_todo(&pic, canister_id, "read", 1);
     assert_eq!(
-        res,
-        WasmResult::Reply("Content First Todo".to_owned().as_bytes().to_vec())
+        res.map(|todo| todo.title),
+        Ok("Content First Todo".to_owned())
+    );
+
+    // test 'update' returns the previous content and 'read' sees the new one.
+    let res = update_todo(&pic, canister_id, 1, "Updated First Todo");
+    assert_eq!(res, Ok("Content First Todo".to_owned()));
+    let res = get_todo(&pic, canister_id, "read", 1);
+    assert_eq!(
+        res.map(|todo| todo.title),
+        Ok("Updated First Todo".to_owned())
+    );
+
+    // test 'delete' and check that 'read' no longer finds the todo.
+    let res = delete_todo(&pic, canister_id, 1);
+    assert_eq!(res.map(|todo| todo.id), Ok(1));
+    let res = get_todo(&pic, canister_id, "read", 1);
+    assert_eq!(res, Err(TodoError::NotFound(1)));
+}
+
+#[test]
+fn test_todos_survive_upgrade() {
+    let (pic, canister_id) = setup();
+    for (i, content) in ["First", "Second", "Third"].iter().enumerate() {
+        assert_eq!(add_todo(&pic, canister_id, content), Ok(i as u64 + 1));
+    }
+
+    pic.upgrade_canister(canister_id, load_todos_wasm(), vec![], None)
+        .expect("Failed to upgrade todo canister");
+
+    assert_eq!(read_title(&pic, canister_id, 1), Ok("First".to_owned()));
+    assert_eq!(read_title(&pic, canister_id, 2), Ok("Second".to_owned()));
+    assert_eq!(read_title(&pic, canister_id, 3), Ok("Third".to_owned()));
+    // the ID counter must continue where it left off
+    assert_eq!(add_todo(&pic, canister_id, "Fourth"), Ok(4));
+}
+
+#[test]
+fn test_many_todos_survive_upgrade() {
+    let (pic, canister_id) = setup_with_capacity(50_000);
+    let total: u64 = 50_000;
+    for i in 1..=total {
+        assert_eq!(add_todo(&pic, canister_id, &format!("Todo {}", i)), Ok(i));
+    }
+
+    pic.upgrade_canister(canister_id, load_todos_wasm(), vec![], None)
+        .expect("Failed to upgrade todo canister");
+
+    for id in [1, 2, 25_000, total - 1, total] {
+        assert_eq!(
+            read_title(&pic, canister_id, id),
+            Ok(format!("Todo {}", id))
+        );
+    }
+    assert_eq!(add_todo(&pic, canister_id, "One more"), Ok(total + 1));
+}
+
+#[test]
+fn test_read_returns_todo_record() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "First").unwrap();
+    add_todo(&pic, canister_id, "Second").unwrap();
+
+    let todo = read_todo(&pic, canister_id, 2).unwrap();
+    assert_eq!(todo.id, 2);
+    assert_eq!(todo.title, "Second");
+    assert!(!todo.done);
+
+    let (todos, next_page) = read_all(&pic, canister_id, 1, 0).unwrap();
+    let ids: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(ids, vec![1, 2]);
+    assert_eq!(next_page, None);
+}
+
+#[test]
+fn test_mark_done_and_undone() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Finish me").unwrap();
+    assert!(!read_todo(&pic, canister_id, id).unwrap().done);
+
+    assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    assert!(read_todo(&pic, canister_id, id).unwrap().done);
+
+    assert_eq!(set_done(&pic, canister_id, "mark_undone", id), Ok(()));
+    assert!(!read_todo(&pic, canister_id, id).unwrap().done);
+
+    assert_eq!(set_done(&pic, canister_id, "toggle_done", id), Ok(()));
+    assert!(read_todo(&pic, canister_id, id).unwrap().done);
+    assert_eq!(set_done(&pic, canister_id, "toggle_done", id), Ok(()));
+    assert!(!read_todo(&pic, canister_id, id).unwrap().done);
+
+    assert_eq!(
+        set_done(&pic, canister_id, "mark_done", 99),
+        Err(TodoError::NotFound(99))
     );
 }
 
-fn add_new_todo(pic: &PocketIc, canister_id: CanisterId, method: &str) -> WasmResult {
-    pic.update_call(
+#[test]
+fn test_content_stats() {
+    let (pic, canister_id) = setup();
+    let content_stats = |id: u64| -> Result<ContentStats, TodoError> {
+        call_query(&pic, canister_id, "content_stats", encode_one(id).unwrap())
+    };
+    let id = add_todo(&pic, canister_id, "Buy milk").unwrap();
+    assert_eq!(
+        content_stats(id),
+        Ok(ContentStats {
+            chars: 8,
+            words: 2,
+            lines: 1
+        })
+    );
+
+    let id = add_described(
+        &pic,
         canister_id,
-        Principal::anonymous(),
-        method,
-        encode_one("Content First Todo").unwrap(),
+        "Café  notes",
+        "first line here\n\n  second\tline\nlast",
     )
-    .expect("Failed to call counter canister")
+    .unwrap();
+    assert_eq!(
+        content_stats(id),
+        Ok(ContentStats {
+            // "é" counts as one character although it takes two bytes
+            chars: 11 + 35,
+            words: 2 + 6,
+            lines: 1 + 4
+        })
+    );
+    assert_eq!(content_stats(99), Err(TodoError::NotFound(99)));
 }
 
-fn get_todo(pic: &PocketIc, canister_id: CanisterId, method: &str, todoid: u16) -> WasmResult {
-    pic.query_call(
+#[test]
+fn test_recently_modified() {
+    let (pic, canister_id) = setup();
+    for i in 1..=4 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+        pic.advance_time(Duration::from_secs(1));
+    }
+    let recent = |limit: u16| -> Vec<u64> {
+        let todos: Vec<Todo> = call_query(
+            &pic,
+            canister_id,
+            "recently_modified",
+            encode_one(limit).unwrap(),
+        );
+        todos.iter().map(|todo| todo.id).collect()
+    };
+    assert_eq!(recent(0), vec![4, 3, 2, 1]);
+
+    // updating the oldest todo moves it to the front
+    update_todo(&pic, canister_id, 1, "Changed").unwrap();
+    assert_eq!(recent(0), vec![1, 4, 3, 2]);
+    assert_eq!(recent(2), vec![1, 4]);
+
+    // other principals only see their own changes
+    let other: Vec<Todo> = call_query_as(
+        &pic,
         canister_id,
-        Principal::anonymous(),
-        method,
-        encode_one(todoid).unwrap(),
-    )
-    .expect("Failed to call counter canister")
+        Principal::from_slice(&[3]),
+        "recently_modified",
+        encode_one(0u16).unwrap(),
+    );
+    assert!(other.is_empty());
 }
 
-fn load_todos_wasm() -> Vec<u8> {
-    // load the todo's was by opening as Vec<u8>
-    // hardcoded wasm binaris DIR
-    let wasm_path ""
-    Vec::new()
+#[test]
+fn test_completed_at() {
+    let (pic, canister_id) = setup();
+    let first = add_todo(&pic, canister_id, "First").unwrap();
+    let second = add_todo(&pic, canister_id, "Second").unwrap();
+    let completed_at = |id: u64| read_todo(&pic, canister_id, id).unwrap().completed_at;
+    let completed_between = |start: u64, end: u64| -> Vec<u64> {
+        let todos: Vec<Todo> = call_query(
+            &pic,
+            canister_id,
+            "completed_between",
+            encode_args((start, end)).unwrap(),
+        );
+        todos.iter().map(|todo| todo.id).collect()
+    };
+    assert_eq!(completed_at(first), None);
+
+    let before = ic_now(&pic);
+    assert_eq!(set_done(&pic, canister_id, "mark_done", first), Ok(()));
+    let done_at = completed_at(first).expect("done todos have a completion time");
+    assert!(done_at >= before);
+    // marking it done again keeps the original time
+    pic.advance_time(Duration::from_secs(60));
+    assert_eq!(set_done(&pic, canister_id, "mark_done", first), Ok(()));
+    assert_eq!(completed_at(first), Some(done_at));
+
+    assert_eq!(set_done(&pic, canister_id, "mark_done", second), Ok(()));
+    let later = completed_at(second).unwrap();
+    assert!(later > done_at);
+    assert_eq!(completed_between(before, later), vec![first, second]);
+    assert_eq!(completed_between(done_at + 1, later), vec![second]);
+    assert!(completed_between(later, done_at).is_empty());
+
+    assert_eq!(set_done(&pic, canister_id, "mark_undone", first), Ok(()));
+    assert_eq!(completed_at(first), None);
+    assert_eq!(completed_between(before, later), vec![second]);
+}
+
+#[test]
+fn test_timestamps() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Original").unwrap();
+    let added = read_todo(&pic, canister_id, id).unwrap();
+    assert!(added.created_at > 0);
+    assert!(added.created_at <= added.updated_at);
+
+    pic.advance_time(Duration::from_secs(60));
+    pic.tick();
+    update_todo(&pic, canister_id, id, "Changed").unwrap();
+    let updated = read_todo(&pic, canister_id, id).unwrap();
+    assert_eq!(updated.created_at, added.created_at);
+    assert!(updated.updated_at > added.updated_at);
+
+    pic.advance_time(Duration::from_secs(60));
+    pic.tick();
+    assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    let done = read_todo(&pic, canister_id, id).unwrap();
+    assert!(done.updated_at > updated.updated_at);
+}
+
+#[test]
+fn test_count() {
+    let (pic, canister_id) = setup();
+    assert_eq!(count(&pic, canister_id), 0);
+    for i in 1..=5 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    assert_eq!(count(&pic, canister_id), 5);
+
+    delete_todo(&pic, canister_id, 3).unwrap();
+    assert_eq!(count(&pic, canister_id), 4);
+}
+
+#[test]
+fn test_is_empty() {
+    let (pic, canister_id) = setup();
+    let is_empty = |caller: Principal| -> bool {
+        call_query_as(
+            &pic,
+            canister_id,
+            caller,
+            "is_empty",
+            encode_one(()).unwrap(),
+        )
+    };
+    assert!(is_empty(Principal::anonymous()));
+
+    let id = add_todo(&pic, canister_id, "First").unwrap();
+    assert!(!is_empty(Principal::anonymous()));
+    // other principals still have no todos
+    assert!(is_empty(Principal::from_slice(&[3])));
+
+    delete_todo(&pic, canister_id, id).unwrap();
+    assert!(is_empty(Principal::anonymous()));
+}
+
+#[test]
+fn test_clear_all() {
+    let (pic, canister_id) = setup();
+    for i in 1..=3 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let res: Result<(), TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "set_reuse_ids",
+        encode_one(true).unwrap(),
+    );
+    res.unwrap();
+    let trashed = add_todo(&pic, canister_id, "Deleted before").unwrap();
+    delete_todo(&pic, canister_id, trashed).unwrap();
+
+    let removed: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "clear_all",
+        encode_one(()).unwrap(),
+    );
+    assert_eq!(removed, Ok(3));
+    assert_eq!(count(&pic, canister_id), 0);
+    // the trash is gone too
+    assert_eq!(
+        undo_delete(&pic, canister_id, trashed),
+        Err(TodoError::NotFound(trashed))
+    );
+    // IDs are not reused after clearing, not even freed ones
+    assert!(add_todo(&pic, canister_id, "After clear").unwrap() > trashed);
+}
+
+#[test]
+fn test_transfer() {
+    let (pic, canister_id) = setup();
+    let alice = Principal::from_slice(&[1]);
+    let bob = Principal::from_slice(&[2]);
+    let carol = Principal::from_slice(&[3]);
+    let transfer = |sender: Principal, id: u64, to: Principal| -> Result<(), TodoError> {
+        call_update_as(
+            &pic,
+            canister_id,
+            sender,
+            "transfer",
+            encode_args((id, to)).unwrap(),
+        )
+    };
+    let gift = add_as(&pic, canister_id, alice, "Plan the trip").unwrap();
+    add_as(&pic, canister_id, alice, "Keep this one").unwrap();
+    let res: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        alice,
+        "add_subtask",
+        encode_args((gift, "Book flights")).unwrap(),
+    );
+    let subtask = res.unwrap();
+    let bobs = add_as(&pic, canister_id, bob, "Bob's own").unwrap();
+    let res: Result<(), TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        alice,
+        "share",
+        encode_args((gift, carol)).unwrap(),
+    );
+    res.unwrap();
+
+    // only the owner may hand a todo over, and only to someone else
+    assert_eq!(transfer(bob, gift, bob), Err(TodoError::NotFound(gift)));
+    assert_eq!(transfer(alice, 99, bob), Err(TodoError::NotFound(99)));
+    assert!(matches!(
+        transfer(alice, gift, Principal::anonymous()),
+        Err(TodoError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        transfer(alice, gift, alice),
+        Err(TodoError::InvalidInput(_))
+    ));
+    assert_eq!(
+        read_as(&pic, canister_id, alice, gift).unwrap().owner,
+        alice
+    );
+
+    assert_eq!(transfer(alice, gift, bob), Ok(()));
+    assert_eq!(
+        read_as(&pic, canister_id, alice, gift),
+        Err(TodoError::NotFound(gift))
+    );
+    assert_eq!(
+        read_as(&pic, canister_id, alice, subtask),
+        Err(TodoError::NotFound(subtask))
+    );
+    let received = read_as(&pic, canister_id, bob, gift).unwrap();
+    assert_eq!(received.title, "Plan the trip");
+    assert_eq!(received.owner, bob);
+    assert_eq!(
+        read_as(&pic, canister_id, bob, subtask).unwrap().parent_id,
+        Some(gift)
+    );
+    // the collaborators of the previous owner lose access
+    assert!(received.shared_with.is_empty());
+    assert_eq!(
+        read_as(&pic, canister_id, carol, gift),
+        Err(TodoError::NotFound(gift))
+    );
+    // received todos go to the end of the receiver's manual order
+    let ordered: Result<Page, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        bob,
+        "read_all_ordered",
+        encode_one(1u16).unwrap(),
+    );
+    let ordered: Vec<u64> = ordered.unwrap().items.iter().map(|todo| todo.id).collect();
+    assert_eq!(ordered, vec![bobs, gift, subtask]);
+
+    let count_as = |sender: Principal| -> u64 {
+        call_query_as(&pic, canister_id, sender, "count", encode_one(()).unwrap())
+    };
+    assert_eq!(count_as(alice), 1);
+    assert_eq!(count_as(bob), 3);
+}
+
+#[test]
+fn test_share() {
+    let (pic, canister_id) = setup();
+    let alice = Principal::from_slice(&[1]);
+    let bob = Principal::from_slice(&[2]);
+    let carol = Principal::from_slice(&[3]);
+    let share =
+        |sender: Principal, method: &str, id: u64, with: Principal| -> Result<(), TodoError> {
+            call_update_as(
+                &pic,
+                canister_id,
+                sender,
+                method,
+                encode_args((id, with)).unwrap(),
+            )
+        };
+    let read_all_ids = |sender: Principal| -> Vec<u64> {
+        let page: Result<Page, TodoError> = call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "read_all",
+            encode_args((1u16, 0u16)).unwrap(),
+        );
+        page.map(|page| page.items.iter().map(|todo| todo.id).collect())
+            .unwrap_or_default()
+    };
+    let shared = add_as(&pic, canister_id, alice, "Shopping list").unwrap();
+    add_as(&pic, canister_id, alice, "Private").unwrap();
+    let own = add_as(&pic, canister_id, bob, "Bob's own").unwrap();
+
+    // only the owner may share
+    assert_eq!(
+        share(bob, "share", shared, bob),
+        Err(TodoError::NotFound(shared))
+    );
+    assert_eq!(share(alice, "share", shared, bob), Ok(()));
+    assert_eq!(share(alice, "share", shared, bob), Ok(()));
+    assert_eq!(
+        read_as(&pic, canister_id, alice, shared)
+            .unwrap()
+            .shared_with,
+        vec![bob]
+    );
+
+    assert_eq!(
+        read_as(&pic, canister_id, bob, shared).unwrap().title,
+        "Shopping list"
+    );
+    assert_eq!(read_all_ids(bob), vec![shared, own]);
+    assert_eq!(
+        read_as(&pic, canister_id, carol, shared),
+        Err(TodoError::NotFound(shared))
+    );
+    assert!(read_all_ids(carol).is_empty());
+    // every other way of reading sees shared todos the same way
+    let ids_of = |todos: Vec<Todo>| -> Vec<u64> { todos.iter().map(|todo| todo.id).collect() };
+    let read_many = |sender: Principal| -> Vec<Option<u64>> {
+        let todos: Vec<Option<Todo>> = call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "read_many",
+            encode_one(vec![shared, own]).unwrap(),
+        );
+        todos
+            .iter()
+            .map(|todo| todo.as_ref().map(|todo| todo.id))
+            .collect()
+    };
+    assert_eq!(read_many(bob), vec![Some(shared), Some(own)]);
+    assert_eq!(read_many(carol), vec![None, None]);
+    let exists_as = |sender: Principal| -> bool {
+        call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "exists",
+            encode_one(shared).unwrap(),
+        )
+    };
+    assert!(exists_as(bob));
+    assert!(!exists_as(carol));
+    for method in ["read_all_by_priority", "read_all_ordered"] {
+        let page = |sender: Principal| -> Vec<u64> {
+            let page: Result<Page, TodoError> =
+                call_query_as(&pic, canister_id, sender, method, encode_one(1u16).unwrap());
+            page.map(|page| ids_of(page.items)).unwrap_or_default()
+        };
+        assert_eq!(page(bob), vec![shared, own], "{}", method);
+        assert!(page(carol).is_empty(), "{}", method);
+    }
+    let search_as = |sender: Principal| -> Vec<u64> {
+        let todos: Vec<Todo> = call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "search",
+            encode_one("shopping").unwrap(),
+        );
+        ids_of(todos)
+    };
+    assert_eq!(search_as(bob), vec![shared]);
+    assert!(search_as(carol).is_empty());
+    let prefix_as = |sender: Principal| -> Vec<u64> {
+        let res: Result<(Vec<Todo>, Option<u16>), TodoError> = call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "search_prefix",
+            encode_args(("shop", 1u16)).unwrap(),
+        );
+        res.map(|(todos, _)| ids_of(todos)).unwrap_or_default()
+    };
+    assert_eq!(prefix_as(bob), vec![shared]);
+    assert!(prefix_as(carol).is_empty());
+    // shared todos are read-only
+    let res: Result<String, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        bob,
+        "update",
+        encode_args((shared, "Hijacked", 1u64)).unwrap(),
+    );
+    assert_eq!(res, Err(TodoError::NotFound(shared)));
+
+    assert_eq!(share(alice, "unshare", shared, bob), Ok(()));
+    assert_eq!(
+        read_as(&pic, canister_id, bob, shared),
+        Err(TodoError::NotFound(shared))
+    );
+    assert_eq!(read_all_ids(bob), vec![own]);
+}
+
+#[test]
+fn test_read_all_deep_page() {
+    let (pic, canister_id) = setup_with_capacity(20_000);
+    for i in 1..=20_000 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+
+    let (todos, next_page) = read_all(&pic, canister_id, 500, 10).unwrap();
+    let ids: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(ids, (4_991..=5_000).collect::<Vec<u64>>());
+    assert_eq!(next_page, Some(501));
+
+    // a gap before the page moves the window by one
+    delete_todo(&pic, canister_id, 1).unwrap();
+    let (todos, _) = read_all(&pic, canister_id, 500, 10).unwrap();
+    let ids: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(ids, (4_992..=5_001).collect::<Vec<u64>>());
+
+    let (todos, next_page) = read_all(&pic, canister_id, 2_000, 10).unwrap();
+    assert_eq!(todos.len(), 9);
+    assert_eq!(next_page, None);
+}
+
+#[test]
+fn test_read_all_with_ids() {
+    let (pic, canister_id) = setup();
+    let titles: Vec<String> = (1..=12).map(|i| format!("Todo {}", i)).collect();
+    let ids = add_many(&pic, canister_id, titles.clone()).unwrap();
+    let read_page = |page: u16| -> Result<TitlePage, TodoError> {
+        call_query(
+            &pic,
+            canister_id,
+            "read_all_with_ids",
+            encode_one(page).unwrap(),
+        )
+    };
+
+    let expected: Vec<(u64, String)> = ids.into_iter().zip(titles).collect();
+    assert_eq!(read_page(1), Ok((expected[..10].to_vec(), Some(2))));
+    assert_eq!(read_page(2), Ok((expected[10..].to_vec(), None)));
+    assert_eq!(read_page(3), Err(TodoError::InvalidPage(3)));
+}
+
+#[test]
+fn test_read_all_page_size() {
+    let (pic, canister_id) = setup_with_capacity(10_000);
+    for i in 1..=150 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+
+    let (todos, next_page) = read_all(&pic, canister_id, 3, 1).unwrap();
+    assert_eq!(todos.len(), 1);
+    assert_eq!(todos[0].id, 3);
+    assert_eq!(next_page, Some(4));
+
+    let (todos, next_page) = read_all(&pic, canister_id, 6, 25).unwrap();
+    assert_eq!(todos.len(), 25);
+    assert_eq!(todos[0].id, 126);
+    assert_eq!(next_page, None);
+
+    // 0 falls back to the default of 10
+    let (todos, next_page) = read_all(&pic, canister_id, 1, 0).unwrap();
+    assert_eq!(todos.len(), 10);
+    assert_eq!(next_page, Some(2));
+
+    // 1000 is clamped to the maximum of 100
+    let (todos, next_page) = read_all(&pic, canister_id, 1, 1000).unwrap();
+    assert_eq!(todos.len(), 100);
+    assert_eq!(next_page, Some(2));
+    let (todos, next_page) = read_all(&pic, canister_id, 2, 1000).unwrap();
+    assert_eq!(todos.len(), 50);
+    assert_eq!(next_page, None);
+}
+
+#[test]
+fn test_read_all_totals() {
+    let (pic, canister_id) = setup();
+    for i in 1..=30 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+
+    // exact multiple of the page size
+    let page = read_page(&pic, canister_id, 1, 10).unwrap();
+    assert_eq!(page.total_items, 30);
+    assert_eq!(page.total_pages, 3);
+
+    // non-exact multiple, the last page is partial
+    let page = read_page(&pic, canister_id, 4, 7).unwrap();
+    assert_eq!(page.items.len(), 7);
+    assert_eq!(page.next_page, Some(5));
+    assert_eq!(page.total_items, 30);
+    assert_eq!(page.total_pages, 5);
+    let page = read_page(&pic, canister_id, 5, 7).unwrap();
+    assert_eq!(page.items.len(), 2);
+    assert_eq!(page.next_page, None);
+}
+
+#[test]
+fn test_todos_are_isolated_per_caller() {
+    let (pic, canister_id) = setup();
+    let alice = Principal::from_slice(&[1]);
+    let bob = Principal::from_slice(&[2]);
+
+    let alice_id: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        alice,
+        "add",
+        encode_one("Alice's").unwrap(),
+    );
+    let alice_id = alice_id.unwrap();
+    let bob_id: Result<u64, TodoError> =
+        call_update_as(&pic, canister_id, bob, "add", encode_one("Bob's").unwrap());
+    let bob_id = bob_id.unwrap();
+
+    let todo: Result<Todo, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        alice,
+        "read",
+        encode_one(alice_id).unwrap(),
+    );
+    assert_eq!(todo.unwrap().owner, alice);
+    let todo: Result<Todo, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        alice,
+        "read",
+        encode_one(bob_id).unwrap(),
+    );
+    assert_eq!(todo, Err(TodoError::NotFound(bob_id)));
+
+    let res: Result<String, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        bob,
+        "update",
+        encode_args((alice_id, "Hijacked")).unwrap(),
+    );
+    assert!(res.is_err());
+    let res: Result<Todo, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        bob,
+        "delete",
+        encode_one(alice_id).unwrap(),
+    );
+    assert!(res.is_err());
+
+    let page: Result<Page, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        bob,
+        "read_all",
+        encode_args((1u16, 0u16)).unwrap(),
+    );
+    let page = page.unwrap();
+    assert_eq!(page.total_items, 1);
+    assert_eq!(page.items[0].id, bob_id);
+    assert_eq!(page.items[0].title, "Bob's");
+
+    let todo: Result<Todo, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        alice,
+        "read",
+        encode_one(alice_id).unwrap(),
+    );
+    assert_eq!(todo.unwrap().title, "Alice's");
+}
+
+#[test]
+fn test_empty_content_is_rejected() {
+    let (pic, canister_id) = setup();
+    assert_eq!(
+        add_todo(&pic, canister_id, ""),
+        Err(TodoError::EmptyContent)
+    );
+    assert_eq!(
+        add_todo(&pic, canister_id, "   "),
+        Err(TodoError::EmptyContent)
+    );
+    assert_eq!(count(&pic, canister_id), 0);
+
+    let id = add_todo(&pic, canister_id, "  Buy milk \t").unwrap();
+    assert_eq!(read_title(&pic, canister_id, id), Ok("Buy milk".to_owned()));
+
+    assert_eq!(
+        update_todo(&pic, canister_id, id, ""),
+        Err(TodoError::EmptyContent)
+    );
+    assert_eq!(
+        update_todo(&pic, canister_id, id, " \n "),
+        Err(TodoError::EmptyContent)
+    );
+    assert_eq!(
+        update_todo(&pic, canister_id, id, "  Buy oat milk  "),
+        Ok("Buy milk".to_owned())
+    );
+    assert_eq!(
+        read_title(&pic, canister_id, id),
+        Ok("Buy oat milk".to_owned())
+    );
+}
+
+#[test]
+fn test_collapse_whitespace() {
+    let (pic, canister_id) = setup();
+    let set_collapse = |caller: Principal, enabled: bool| -> Result<(), TodoError> {
+        call_update_as(
+            &pic,
+            canister_id,
+            caller,
+            "set_collapse_whitespace",
+            encode_one(enabled).unwrap(),
+        )
+    };
+
+    // off by default
+    let kept = add_todo(&pic, canister_id, "a   b\tc").unwrap();
+    assert_eq!(
+        read_title(&pic, canister_id, kept),
+        Ok("a   b\tc".to_owned())
+    );
+
+    assert_eq!(
+        set_collapse(Principal::anonymous(), true),
+        Err(TodoError::Unauthorized)
+    );
+    assert_eq!(set_collapse(admin(), true), Ok(()));
+    // titles that are already stored don't change
+    assert_eq!(
+        read_title(&pic, canister_id, kept),
+        Ok("a   b\tc".to_owned())
+    );
+    let id = add_todo(&pic, canister_id, "a   b\tc").unwrap();
+    assert_eq!(read_title(&pic, canister_id, id), Ok("a b c".to_owned()));
+    // line breaks are kept as they are, only the runs between them shrink
+    update_todo(&pic, canister_id, id, "first  line \r\n\t second").unwrap();
+    assert_eq!(
+        read_title(&pic, canister_id, id),
+        Ok("first line \r\n second".to_owned())
+    );
+
+    assert_eq!(set_collapse(admin(), false), Ok(()));
+    update_todo(&pic, canister_id, id, " x  y ").unwrap();
+    assert_eq!(read_title(&pic, canister_id, id), Ok("x  y".to_owned()));
+}
+
+#[test]
+fn test_content_length_limit() {
+    let (pic, canister_id) = setup();
+
+    // multi-byte characters count as a single character
+    let max = "é".repeat(1000);
+    let id = add_todo(&pic, canister_id, &max).unwrap();
+    assert_eq!(read_title(&pic, canister_id, id), Ok(max.clone()));
+    assert_eq!(
+        add_todo(&pic, canister_id, &"é".repeat(1001)),
+        Err(TodoError::TooLong(1000))
+    );
+
+    assert_eq!(
+        update_todo(&pic, canister_id, id, &"a".repeat(1000)),
+        Ok(max)
+    );
+    assert_eq!(
+        update_todo(&pic, canister_id, id, &"a".repeat(1001)),
+        Err(TodoError::TooLong(1000))
+    );
+}
+
+#[test]
+fn test_add_many() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "Existing").unwrap();
+
+    let contents: Vec<String> = (1..=5).map(|i| format!("Batch {}", i)).collect();
+    assert_eq!(
+        add_many(&pic, canister_id, contents),
+        Ok(vec![2, 3, 4, 5, 6])
+    );
+    assert_eq!(read_title(&pic, canister_id, 4), Ok("Batch 3".to_owned()));
+
+    // one invalid entry rejects the whole batch
+    let contents = vec!["Fine".to_owned(), "  ".to_owned(), "Also fine".to_owned()];
+    assert_eq!(
+        add_many(&pic, canister_id, contents),
+        Err(TodoError::InvalidEntry {
+            index: 1,
+            error: Box::new(TodoError::EmptyContent)
+        })
+    );
+    assert_eq!(count(&pic, canister_id), 6);
+}
+
+#[test]
+fn test_update_many() {
+    let (pic, canister_id) = setup();
+    for i in 1..=3 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let update_many = |edits: Vec<(u64, &str)>| -> Result<u64, TodoError> {
+        call_update(&pic, canister_id, "update_many", encode_one(edits).unwrap())
+    };
+
+    assert_eq!(update_many(vec![(1, "First"), (3, "Third")]), Ok(2));
+    assert_eq!(read_title(&pic, canister_id, 1), Ok("First".to_owned()));
+    assert_eq!(read_title(&pic, canister_id, 2), Ok("Todo 2".to_owned()));
+    assert_eq!(read_title(&pic, canister_id, 3), Ok("Third".to_owned()));
+
+    // one missing ID rejects the whole batch
+    assert_eq!(
+        update_many(vec![(2, "Second"), (99, "Missing")]),
+        Err(TodoError::InvalidEntry {
+            index: 1,
+            error: Box::new(TodoError::NotFound(99))
+        })
+    );
+    assert_eq!(
+        update_many(vec![(2, "Second"), (3, " ")]),
+        Err(TodoError::InvalidEntry {
+            index: 1,
+            error: Box::new(TodoError::EmptyContent)
+        })
+    );
+    assert_eq!(read_title(&pic, canister_id, 2), Ok("Todo 2".to_owned()));
+    assert_eq!(read_title(&pic, canister_id, 3), Ok("Third".to_owned()));
+
+    // a repeated ID takes the last title and is changed once
+    let version = read_todo(&pic, canister_id, 2).unwrap().version;
+    assert_eq!(update_many(vec![(2, "Draft"), (2, "Second")]), Ok(1));
+    let second = read_todo(&pic, canister_id, 2).unwrap();
+    assert_eq!(second.title, "Second");
+    assert_eq!(second.version, version + 1);
+}
+
+#[test]
+fn test_delete_many() {
+    let (pic, canister_id) = setup();
+    for i in 1..=4 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+
+    let deleted: Vec<u64> = call_update(
+        &pic,
+        canister_id,
+        "delete_many",
+        encode_one(vec![4u64, 99, 2, 2]).unwrap(),
+    );
+    assert_eq!(deleted, vec![4, 2]);
+    assert_eq!(count(&pic, canister_id), 2);
+    assert_eq!(read_todo(&pic, canister_id, 2), Err(TodoError::NotFound(2)));
+
+    // deleting the same ids again is a no-op
+    let deleted: Vec<u64> = call_update(
+        &pic,
+        canister_id,
+        "delete_many",
+        encode_one(vec![4u64, 2]).unwrap(),
+    );
+    assert!(deleted.is_empty());
+    assert_eq!(count(&pic, canister_id), 2);
+}
+
+#[test]
+fn test_read_many() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "First").unwrap();
+    add_todo(&pic, canister_id, "Second").unwrap();
+
+    let todos: Vec<Option<Todo>> = call_query(
+        &pic,
+        canister_id,
+        "read_many",
+        encode_one(vec![1u64, 99, 2]).unwrap(),
+    );
+    assert_eq!(todos.len(), 3);
+    assert_eq!(todos[0].as_ref().unwrap().title, "First");
+    assert_eq!(todos[1], None);
+    assert_eq!(todos[2].as_ref().unwrap().title, "Second");
+}
+
+#[test]
+fn test_read_range() {
+    let (pic, canister_id) = setup();
+    for i in 1..=10 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let read_range = |sender: Principal, start: u64, end: u64| -> Vec<u64> {
+        let todos: Vec<Todo> = call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "read_range",
+            encode_args((start, end)).unwrap(),
+        );
+        todos.iter().map(|todo| todo.id).collect()
+    };
+
+    assert_eq!(read_range(Principal::anonymous(), 3, 6), vec![3, 4, 5, 6]);
+    assert_eq!(read_range(Principal::anonymous(), 10, 20), vec![10]);
+    assert_eq!(read_range(Principal::anonymous(), 6, 6), vec![6]);
+    assert!(read_range(Principal::anonymous(), 6, 3).is_empty());
+    // other principals' todos are left out
+    assert!(read_range(Principal::from_slice(&[3]), 1, 10).is_empty());
+}
+
+#[test]
+fn test_find_exact() {
+    let (pic, canister_id) = setup();
+    let find_exact = |title: &str| -> Vec<u64> {
+        call_query(&pic, canister_id, "find_exact", encode_one(title).unwrap())
+    };
+    let first = add_todo(&pic, canister_id, "Buy milk").unwrap();
+    add_todo(&pic, canister_id, "Buy milk and eggs").unwrap();
+    let second = add_todo(&pic, canister_id, "Buy milk").unwrap();
+
+    assert_eq!(find_exact("Buy milk"), vec![first, second]);
+    assert_eq!(find_exact("  Buy milk "), vec![first, second]);
+    assert!(find_exact("buy milk").is_empty());
+    assert!(find_exact("Buy").is_empty());
+    assert!(find_exact("").is_empty());
+}
+
+#[test]
+fn test_search() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "Buy MILK").unwrap();
+    add_todo(&pic, canister_id, "Walk the dog").unwrap();
+    add_todo(&pic, canister_id, "Oat milk for later").unwrap();
+
+    let ids: Vec<u64> = search(&pic, canister_id, "Milk")
+        .iter()
+        .map(|todo| todo.id)
+        .collect();
+    assert_eq!(ids, vec![1, 3]);
+    assert!(search(&pic, canister_id, "cat").is_empty());
+    assert!(search(&pic, canister_id, "").is_empty());
+}
+
+#[test]
+fn test_search_ignores_diacritics() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "Meet at the Café").unwrap();
+    add_todo(&pic, canister_id, "Stop being naïve").unwrap();
+    add_todo(&pic, canister_id, "Cafeteria duty").unwrap();
+    let search_ids = |term: &str| -> Vec<u64> {
+        search(&pic, canister_id, term)
+            .iter()
+            .map(|todo| todo.id)
+            .collect()
+    };
+
+    assert_eq!(search_ids("cafe"), vec![1, 3]);
+    assert_eq!(search_ids("CAFÉ"), vec![1, 3]);
+    assert_eq!(search_ids("naive"), vec![2]);
+    assert_eq!(search_ids("naïve"), vec![2]);
+    // the stored title keeps its accents
+    assert_eq!(
+        search(&pic, canister_id, "cafe")[0].title,
+        "Meet at the Café"
+    );
+}
+
+#[test]
+fn test_search_prefix() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "Buy milk").unwrap();
+    add_todo(&pic, canister_id, "Go buy bread").unwrap();
+    for i in 1..=10 {
+        add_todo(&pic, canister_id, &format!("buying list {}", i)).unwrap();
+    }
+
+    let (todos, next_page) = search_prefix(&pic, canister_id, "buy", 1).unwrap();
+    assert_eq!(todos.len(), 10);
+    assert_eq!(todos[0].title, "Buy milk");
+    assert!(todos.iter().all(|todo| todo.title != "Go buy bread"));
+    assert_eq!(next_page, Some(2));
+
+    let (todos, next_page) = search_prefix(&pic, canister_id, "buy", 2).unwrap();
+    assert_eq!(todos.len(), 1);
+    assert_eq!(next_page, None);
+
+    assert_eq!(
+        search_prefix(&pic, canister_id, "milk", 1),
+        Err(TodoError::InvalidPage(1))
+    );
+}
+
+#[test]
+fn test_exists() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Here").unwrap();
+    assert!(exists(&pic, canister_id, id));
+
+    delete_todo(&pic, canister_id, id).unwrap();
+    assert!(!exists(&pic, canister_id, id));
+    assert!(!exists(&pic, canister_id, 99));
+}
+
+#[test]
+fn test_delete_returns_todo() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Short lived").unwrap();
+
+    let deleted = delete_todo(&pic, canister_id, id).unwrap();
+    assert_eq!(deleted.id, id);
+    assert_eq!(deleted.title, "Short lived");
+    assert_eq!(
+        delete_todo(&pic, canister_id, id),
+        Err(TodoError::NotFound(id))
+    );
+}
+
+#[test]
+fn test_update_returns_previous_content() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Before").unwrap();
+
+    assert_eq!(
+        update_todo(&pic, canister_id, id, "After"),
+        Ok("Before".to_owned())
+    );
+    assert_eq!(read_title(&pic, canister_id, id), Ok("After".to_owned()));
+}
+
+#[test]
+fn test_undo_delete() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Oops").unwrap();
+    add_todo(&pic, canister_id, "Keep").unwrap();
+    delete_todo(&pic, canister_id, id).unwrap();
+    assert!(!exists(&pic, canister_id, id));
+
+    let restored = undo_delete(&pic, canister_id, id).unwrap();
+    assert_eq!(restored.id, id);
+    assert_eq!(restored.title, "Oops");
+    assert_eq!(read_title(&pic, canister_id, id), Ok("Oops".to_owned()));
+
+    // it left the trash, and "Keep" was never deleted
+    assert_eq!(
+        undo_delete(&pic, canister_id, id),
+        Err(TodoError::NotFound(id))
+    );
+    assert_eq!(
+        undo_delete(&pic, canister_id, 2),
+        Err(TodoError::NotFound(2))
+    );
+    assert_eq!(
+        undo_delete(&pic, canister_id, 99),
+        Err(TodoError::NotFound(99))
+    );
+}
+
+#[test]
+fn test_trash_evicts_oldest() {
+    let (pic, canister_id) = setup_with_capacity(10_000);
+    let other = Principal::from_slice(&[3]);
+    let theirs: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        other,
+        "add",
+        encode_args(("Theirs", "")).unwrap(),
+    );
+    let theirs = theirs.unwrap();
+    let deleted: Result<Todo, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        other,
+        "delete",
+        encode_one(theirs).unwrap(),
+    );
+    assert!(deleted.is_ok());
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=101).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    for id in &ids {
+        delete_todo(&pic, canister_id, *id).unwrap();
+    }
+
+    assert_eq!(
+        undo_delete(&pic, canister_id, ids[0]),
+        Err(TodoError::NotFound(ids[0]))
+    );
+    assert!(undo_delete(&pic, canister_id, ids[1]).is_ok());
+    assert!(undo_delete(&pic, canister_id, ids[100]).is_ok());
+
+    // the trash is capped per principal, the other one can still undo its delete
+    let restored: Result<Todo, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        other,
+        "undo_delete",
+        encode_one(theirs).unwrap(),
+    );
+    assert_eq!(restored.map(|todo| todo.title), Ok("Theirs".to_owned()));
+}
+
+#[test]
+fn test_read_all_by_priority() {
+    let (pic, canister_id) = setup();
+    add_many(
+        &pic,
+        canister_id,
+        (1..=12).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    assert_eq!(
+        read_todo(&pic, canister_id, 1).unwrap().priority,
+        Priority::Medium
+    );
+    for (id, priority) in [
+        (2, Priority::Low),
+        (5, Priority::High),
+        (9, Priority::High),
+        (11, Priority::Low),
+    ] {
+        assert_eq!(set_priority(&pic, canister_id, id, priority), Ok(()));
+    }
+    assert_eq!(
+        set_priority(&pic, canister_id, 99, Priority::High),
+        Err(TodoError::NotFound(99))
+    );
+
+    let first: Result<Page, TodoError> = call_query(
+        &pic,
+        canister_id,
+        "read_all_by_priority",
+        encode_one(1u16).unwrap(),
+    );
+    let second: Result<Page, TodoError> = call_query(
+        &pic,
+        canister_id,
+        "read_all_by_priority",
+        encode_one(2u16).unwrap(),
+    );
+    let (first, second) = (first.unwrap(), second.unwrap());
+    let ids: Vec<u64> = first
+        .items
+        .iter()
+        .chain(second.items.iter())
+        .map(|todo| todo.id)
+        .collect();
+    assert_eq!(ids, vec![5, 9, 1, 3, 4, 6, 7, 8, 10, 12, 2, 11]);
+    assert_eq!(first.next_page, Some(2));
+    assert_eq!(second.next_page, None);
+}
+
+#[test]
+fn test_overdue() {
+    let (pic, canister_id) = setup();
+    let past = add_todo(&pic, canister_id, "Past").unwrap();
+    let soon = add_todo(&pic, canister_id, "Soon").unwrap();
+    let finished = add_todo(&pic, canister_id, "Finished").unwrap();
+    add_todo(&pic, canister_id, "No deadline").unwrap();
+
+    let now = ic_now(&pic);
+    let hour = Duration::from_secs(3600).as_nanos() as u64;
+    assert_eq!(set_due(&pic, canister_id, past, Some(now - hour)), Ok(()));
+    assert_eq!(set_due(&pic, canister_id, soon, Some(now + hour)), Ok(()));
+    assert_eq!(
+        set_due(&pic, canister_id, finished, Some(now - hour)),
+        Ok(())
+    );
+    assert_eq!(set_done(&pic, canister_id, "mark_done", finished), Ok(()));
+    assert_eq!(
+        set_due(&pic, canister_id, 99, None),
+        Err(TodoError::NotFound(99))
+    );
+    assert_eq!(
+        read_todo(&pic, canister_id, past).unwrap().due_at,
+        Some(now - hour)
+    );
+    assert_eq!(overdue_ids(&pic, canister_id), vec![past]);
+
+    pic.advance_time(Duration::from_secs(2 * 3600));
+    pic.tick();
+    assert_eq!(overdue_ids(&pic, canister_id), vec![past, soon]);
+
+    assert_eq!(set_due(&pic, canister_id, past, None), Ok(()));
+    assert_eq!(overdue_ids(&pic, canister_id), vec![soon]);
+}
+
+#[test]
+fn test_due_within() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        [
+            "Tonight",
+            "Tomorrow",
+            "Next week",
+            "Yesterday",
+            "Done",
+            "Someday",
+        ]
+        .map(String::from)
+        .to_vec(),
+    )
+    .unwrap();
+    let now = ic_now(&pic);
+    let hour = Duration::from_secs(3600).as_nanos() as u64;
+    for (id, due_at) in ids.iter().zip([
+        now + 20 * hour,
+        now + 4 * hour,
+        now + 7 * 24 * hour,
+        now - 24 * hour,
+        now + hour,
+    ]) {
+        assert_eq!(set_due(&pic, canister_id, *id, Some(due_at)), Ok(()));
+    }
+    assert_eq!(set_done(&pic, canister_id, "mark_done", ids[4]), Ok(()));
+    let due_within = |window: u64| -> Vec<u64> {
+        let todos: Vec<Todo> =
+            call_query(&pic, canister_id, "due_within", encode_one(window).unwrap());
+        todos.iter().map(|todo| todo.id).collect()
+    };
+
+    assert_eq!(due_within(24 * hour), vec![ids[1], ids[0]]);
+    assert_eq!(due_within(u64::MAX), vec![ids[1], ids[0], ids[2]]);
+    assert!(due_within(hour).is_empty());
+}
+
+#[test]
+fn test_overdue_paged() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=14).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let now = ic_now(&pic);
+    let hour = Duration::from_secs(3600).as_nanos() as u64;
+    // the todos are due one hour apart, the later ones longer ago
+    for (i, &id) in ids[..12].iter().enumerate() {
+        let due_at = now - (i as u64 + 1) * hour;
+        assert_eq!(set_due(&pic, canister_id, id, Some(due_at)), Ok(()));
+    }
+    assert_eq!(set_done(&pic, canister_id, "mark_done", ids[0]), Ok(()));
+    assert_eq!(
+        set_due(&pic, canister_id, ids[12], Some(now + hour)),
+        Ok(())
+    );
+    let overdue_page = |page: u16| -> Result<Page, TodoError> {
+        call_query(
+            &pic,
+            canister_id,
+            "overdue_paged",
+            encode_one(page).unwrap(),
+        )
+    };
+
+    let first = overdue_page(1).unwrap();
+    let first_ids: Vec<u64> = first.items.iter().map(|todo| todo.id).collect();
+    let expected: Vec<u64> = ids[1..12].iter().rev().copied().collect();
+    assert_eq!(first_ids, expected[..10]);
+    assert_eq!(first.next_page, Some(2));
+    assert_eq!(first.total_items, 11);
+    assert_eq!(first.total_pages, 2);
+    let second = overdue_page(2).unwrap();
+    let second_ids: Vec<u64> = second.items.iter().map(|todo| todo.id).collect();
+    assert_eq!(second_ids, expected[10..]);
+    assert_eq!(second.next_page, None);
+    assert_eq!(overdue_page(3).unwrap_err(), TodoError::InvalidPage(3));
+}
+
+#[test]
+fn test_snooze() {
+    let (pic, canister_id) = setup();
+    let due = add_todo(&pic, canister_id, "Due").unwrap();
+    let undated = add_todo(&pic, canister_id, "No deadline").unwrap();
+    let snooze = |id: u64, by_nanos: u64| -> Result<u64, TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "snooze",
+            encode_args((id, by_nanos)).unwrap(),
+        )
+    };
+    let now = ic_now(&pic);
+    let hour = Duration::from_secs(3600).as_nanos() as u64;
+
+    assert_eq!(set_due(&pic, canister_id, due, Some(now - hour)), Ok(()));
+    assert_eq!(snooze(due, 3 * hour), Ok(now + 2 * hour));
+    assert_eq!(
+        read_todo(&pic, canister_id, due).unwrap().due_at,
+        Some(now + 2 * hour)
+    );
+    assert!(overdue_ids(&pic, canister_id).is_empty());
+
+    // a missing due date counts as the time of the call
+    let before = ic_now(&pic);
+    let snoozed = snooze(undated, hour).unwrap();
+    assert!((before + hour..=ic_now(&pic) + hour).contains(&snoozed));
+    assert_eq!(
+        read_todo(&pic, canister_id, undated).unwrap().due_at,
+        Some(snoozed)
+    );
+    assert_eq!(snooze(99, hour), Err(TodoError::NotFound(99)));
+}
+
+#[test]
+fn test_recurring_todos() {
+    let (pic, canister_id) = setup();
+    let habit = add_todo(&pic, canister_id, "Water plants").unwrap();
+    let once = add_todo(&pic, canister_id, "File taxes").unwrap();
+    let mark_done = |id: u64| -> Result<Option<u64>, TodoError> {
+        call_update(&pic, canister_id, "mark_done", encode_one(id).unwrap())
+    };
+    let set_recurrence = |id: u64, recurrence: Option<u64>| -> Result<(), TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "set_recurrence",
+            encode_args((id, recurrence)).unwrap(),
+        )
+    };
+    let day = Duration::from_secs(24 * 3600).as_nanos() as u64;
+    let now = ic_now(&pic);
+
+    assert_eq!(set_recurrence(habit, Some(day)), Ok(()));
+    assert_eq!(set_due(&pic, canister_id, habit, Some(now)), Ok(()));
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", habit, "home"),
+        Ok(())
+    );
+    assert_eq!(set_recurrence(99, Some(day)), Err(TodoError::NotFound(99)));
+
+    // non-recurring todos behave as before
+    assert_eq!(mark_done(once), Ok(None));
+    assert!(read_todo(&pic, canister_id, once).unwrap().done);
+
+    let next = mark_done(habit)
+        .unwrap()
+        .expect("a recurring todo comes back");
+    let completed = read_todo(&pic, canister_id, habit).unwrap();
+    assert!(completed.done);
+    assert_eq!(completed.recurrence, None);
+    let fresh = read_todo(&pic, canister_id, next).unwrap();
+    assert!(!fresh.done);
+    assert_eq!(fresh.title, "Water plants");
+    assert_eq!(fresh.tags, vec!["home".to_owned()]);
+    assert_eq!(fresh.due_at, Some(now + day));
+    assert_eq!(fresh.recurrence, Some(day));
+    assert_eq!(count(&pic, canister_id), 3);
+
+    // completing the old instance again doesn't create another copy
+    assert_eq!(mark_done(habit), Ok(None));
+    assert_eq!(count(&pic, canister_id), 3);
+    let after_next = mark_done(next).unwrap().unwrap();
+    assert_eq!(
+        read_todo(&pic, canister_id, after_next).unwrap().due_at,
+        Some(now + 2 * day)
+    );
+}
+
+#[test]
+fn test_add_tag_many() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        vec!["First".to_owned(), "Second".to_owned(), "Third".to_owned()],
+    )
+    .unwrap();
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", ids[1], "urgent"),
+        Ok(())
+    );
+    let version = read_todo(&pic, canister_id, ids[1]).unwrap().version;
+    let add_tag_many = |ids: Vec<u64>, tag: &str| -> u64 {
+        call_update(
+            &pic,
+            canister_id,
+            "add_tag_many",
+            encode_args((ids, tag)).unwrap(),
+        )
+    };
+
+    assert_eq!(add_tag_many(ids.clone(), " Urgent "), 2);
+    assert_eq!(by_tag(&pic, canister_id, "urgent"), ids);
+    assert_eq!(
+        read_todo(&pic, canister_id, ids[1]).unwrap().tags,
+        vec!["urgent".to_string()]
+    );
+    assert_eq!(
+        read_todo(&pic, canister_id, ids[1]).unwrap().version,
+        version
+    );
+
+    // unknown IDs, repeated IDs and empty tags change nothing
+    assert_eq!(add_tag_many(vec![99, ids[0], ids[0]], "home"), 1);
+    assert_eq!(add_tag_many(ids.clone(), "  "), 0);
+    assert_eq!(by_tag(&pic, canister_id, "home"), vec![ids[0]]);
+}
+
+#[test]
+fn test_rename_tag() {
+    let (pic, canister_id) = setup();
+    let rename = |old: &str, new: &str| -> Result<u64, TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "rename_tag",
+            encode_args((old, new)).unwrap(),
+        )
+    };
+    let tagged = [
+        vec!["home", "chores", "errands"],
+        vec!["chores", "house"],
+        vec!["house"],
+        vec!["work"],
+    ];
+    let mut ids = Vec::new();
+    for (i, tags) in tagged.iter().enumerate() {
+        let id = add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+        for tag in tags {
+            assert_eq!(edit_tag(&pic, canister_id, "add_tag", id, tag), Ok(()));
+        }
+        ids.push(id);
+    }
+    let tags = |id: u64| read_todo(&pic, canister_id, id).unwrap().tags;
+
+    assert_eq!(rename("Chores", "House"), Ok(2));
+    // the new tag takes the place of the old one
+    assert_eq!(tags(ids[0]), vec!["home", "house", "errands"]);
+    // todos that already had the new tag don't get it twice
+    assert_eq!(tags(ids[1]), vec!["house"]);
+    assert_eq!(tags(ids[2]), vec!["house"]);
+    assert_eq!(tags(ids[3]), vec!["work"]);
+    assert_eq!(by_tag(&pic, canister_id, "house"), ids[..3].to_vec());
+    assert!(by_tag(&pic, canister_id, "chores").is_empty());
+
+    assert_eq!(rename("chores", "house"), Ok(0));
+    assert_eq!(rename("work", " Work "), Ok(0));
+    assert_eq!(rename("work", "  "), Err(TodoError::EmptyContent));
+}
+
+#[test]
+fn test_list_tags() {
+    let (pic, canister_id) = setup();
+    let list_tags = |page: u16| -> Result<(Vec<String>, Option<u16>), TodoError> {
+        call_query(&pic, canister_id, "list_tags", encode_one(page).unwrap())
+    };
+    assert_eq!(list_tags(1), Err(TodoError::InvalidPage(1)));
+
+    // 25 distinct tags spread over 5 todos, some of them on two todos
+    for i in 0..5 {
+        let id = add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+        for j in 0..5 {
+            let tag = format!("tag{:02}", i * 5 + j);
+            assert_eq!(edit_tag(&pic, canister_id, "add_tag", id, &tag), Ok(()));
+        }
+        assert_eq!(edit_tag(&pic, canister_id, "add_tag", id, "tag00"), Ok(()));
+    }
+    let expected: Vec<String> = (0..25).map(|i| format!("tag{:02}", i)).collect();
+    assert_eq!(list_tags(1), Ok((expected[..10].to_vec(), Some(2))));
+    assert_eq!(list_tags(2), Ok((expected[10..20].to_vec(), Some(3))));
+    assert_eq!(list_tags(3), Ok((expected[20..].to_vec(), None)));
+    assert_eq!(list_tags(4), Err(TodoError::InvalidPage(4)));
+}
+
+#[test]
+fn test_tag_counts() {
+    let (pic, canister_id) = setup();
+    let tag_counts = || -> Vec<(String, u64)> {
+        call_query(&pic, canister_id, "tag_counts", encode_one(()).unwrap())
+    };
+    assert!(tag_counts().is_empty());
+
+    let tagged = [
+        vec!["home", "errands"],
+        vec!["work", "home"],
+        vec!["errands", "home"],
+        vec!["work"],
+        vec!["garden"],
+        vec![],
+    ];
+    for (i, tags) in tagged.iter().enumerate() {
+        let id = add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+        for tag in tags {
+            assert_eq!(edit_tag(&pic, canister_id, "add_tag", id, tag), Ok(()));
+        }
+    }
+    // ties are ordered by name
+    assert_eq!(
+        tag_counts(),
+        vec![
+            ("home".to_string(), 3),
+            ("errands".to_string(), 2),
+            ("work".to_string(), 2),
+            ("garden".to_string(), 1),
+        ]
+    );
+
+    // other principals have their own counts
+    let other: Vec<(String, u64)> = call_query_as(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "tag_counts",
+        encode_one(()).unwrap(),
+    );
+    assert!(other.is_empty());
+}
+
+#[test]
+fn test_tags() {
+    let (pic, canister_id) = setup();
+    let groceries = add_todo(&pic, canister_id, "Groceries").unwrap();
+    let report = add_todo(&pic, canister_id, "Report").unwrap();
+    add_todo(&pic, canister_id, "Untagged").unwrap();
+
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", groceries, "Home"),
+        Ok(())
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", groceries, " home "),
+        Ok(())
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", groceries, "errands"),
+        Ok(())
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", report, "work"),
+        Ok(())
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", report, "HOME"),
+        Ok(())
+    );
+    assert_eq!(
+        read_todo(&pic, canister_id, groceries).unwrap().tags,
+        vec!["home".to_string(), "errands".to_string()]
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", report, "  "),
+        Err(TodoError::EmptyContent)
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", 99, "home"),
+        Err(TodoError::NotFound(99))
+    );
+
+    assert_eq!(by_tag(&pic, canister_id, "Home"), vec![groceries, report]);
+    assert_eq!(by_tag(&pic, canister_id, "work"), vec![report]);
+    assert!(by_tag(&pic, canister_id, "garden").is_empty());
+
+    assert_eq!(
+        edit_tag(&pic, canister_id, "remove_tag", report, "Home"),
+        Ok(())
+    );
+    assert_eq!(
+        edit_tag(&pic, canister_id, "remove_tag", report, "garden"),
+        Ok(())
+    );
+    assert_eq!(by_tag(&pic, canister_id, "home"), vec![groceries]);
+    assert_eq!(
+        read_todo(&pic, canister_id, report).unwrap().tags,
+        vec!["work".to_string()]
+    );
+}
+
+#[test]
+fn test_get_or_create() {
+    let (pic, canister_id) = setup();
+    let get_or_create = |title: &str| -> Result<u64, TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "get_or_create",
+            encode_one(title).unwrap(),
+        )
+    };
+    add_todo(&pic, canister_id, "Other").unwrap();
+
+    let id = get_or_create("Buy milk").unwrap();
+    assert_eq!(get_or_create("Buy milk"), Ok(id));
+    assert_eq!(get_or_create("  Buy milk "), Ok(id));
+    assert_eq!(count(&pic, canister_id), 2);
+    assert_eq!(read_title(&pic, canister_id, id), Ok("Buy milk".to_owned()));
+
+    // the comparison is exact apart from whitespace
+    assert!(get_or_create("buy milk").is_ok_and(|other| other != id));
+    assert_eq!(get_or_create(" "), Err(TodoError::EmptyContent));
+    // todos of other principals don't count
+    let other: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "get_or_create",
+        encode_one("Buy milk").unwrap(),
+    );
+    assert!(other.is_ok_and(|other| other != id));
+}
+
+#[test]
+fn test_create() {
+    let (pic, canister_id) = setup();
+    let create = |input: CreateTodo| -> Result<u64, TodoError> {
+        call_update(&pic, canister_id, "create", encode_one(input).unwrap())
+    };
+    let due_at = ic_now(&pic) + 1_000;
+
+    let id = create(CreateTodo {
+        title: "  Plan the trip ".to_owned(),
+        description: " Flights and hotel ".to_owned(),
+        tags: vec!["Travel".to_owned(), " travel".to_owned(), "work".to_owned()],
+        priority: Priority::High,
+        due_at: Some(due_at),
+    })
+    .unwrap();
+    let todo = read_todo(&pic, canister_id, id).unwrap();
+    assert_eq!(todo.id, id);
+    assert_eq!(todo.owner, Principal::anonymous());
+    assert_eq!(todo.title, "Plan the trip");
+    assert_eq!(todo.description, "Flights and hotel");
+    assert_eq!(todo.tags, vec!["travel".to_owned(), "work".to_owned()]);
+    assert_eq!(todo.priority, Priority::High);
+    assert_eq!(todo.due_at, Some(due_at));
+    assert!(!todo.done);
+    assert!(!todo.pinned);
+    assert_eq!(todo.parent_id, None);
+    assert_eq!(todo.version, 0);
+    assert_eq!(todo.created_at, todo.updated_at);
+
+    // nothing is stored if any field is invalid
+    let res = create(CreateTodo {
+        title: "Valid".to_owned(),
+        description: String::new(),
+        tags: vec!["ok".to_owned(), " ".to_owned()],
+        priority: Priority::Low,
+        due_at: None,
+    });
+    assert_eq!(res, Err(TodoError::EmptyContent));
+    let res = create(CreateTodo {
+        title: " ".to_owned(),
+        description: String::new(),
+        tags: Vec::new(),
+        priority: Priority::Low,
+        due_at: None,
+    });
+    assert_eq!(res, Err(TodoError::EmptyContent));
+    assert_eq!(count(&pic, canister_id), 1);
+}
+
+#[test]
+fn test_patch() {
+    let (pic, canister_id) = setup();
+    let id = add_described(&pic, canister_id, "Original", "Notes").unwrap();
+    assert_eq!(set_priority(&pic, canister_id, id, Priority::High), Ok(()));
+    assert_eq!(set_due(&pic, canister_id, id, Some(42)), Ok(()));
+    let patch = |patch: TodoPatch| -> Result<(), TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "patch",
+            encode_args((id, patch)).unwrap(),
+        )
+    };
+    let before = read_todo(&pic, canister_id, id).unwrap();
+
+    // only `done`
+    let res = patch(TodoPatch {
+        done: Some(true),
+        ..Default::default()
+    });
+    assert_eq!(res, Ok(()));
+    let after = read_todo(&pic, canister_id, id).unwrap();
+    assert!(after.done);
+    assert_eq!(after.title, before.title);
+    assert_eq!(after.description, before.description);
+    assert_eq!(after.priority, before.priority);
+    assert_eq!(after.due_at, before.due_at);
+
+    // only `title`
+    let res = patch(TodoPatch {
+        title: Some(" Renamed ".to_owned()),
+        ..Default::default()
+    });
+    assert_eq!(res, Ok(()));
+    let renamed = read_todo(&pic, canister_id, id).unwrap();
+    assert_eq!(renamed.title, "Renamed");
+    assert!(renamed.done);
+    assert_eq!(renamed.priority, Priority::High);
+    assert_eq!(renamed.due_at, Some(42));
+
+    // `Some(None)` clears the due date, `None` keeps it
+    let res = patch(TodoPatch {
+        due_at: Some(None),
+        ..Default::default()
+    });
+    assert_eq!(res, Ok(()));
+    assert_eq!(read_todo(&pic, canister_id, id).unwrap().due_at, None);
+
+    let res = patch(TodoPatch {
+        title: Some(" ".to_owned()),
+        done: Some(false),
+        ..Default::default()
+    });
+    assert_eq!(res, Err(TodoError::EmptyContent));
+    assert!(read_todo(&pic, canister_id, id).unwrap().done);
+    let res: Result<(), TodoError> = call_update(
+        &pic,
+        canister_id,
+        "patch",
+        encode_args((99u64, TodoPatch::default())).unwrap(),
+    );
+    assert_eq!(res, Err(TodoError::NotFound(99)));
+}
+
+#[test]
+fn test_title_and_description() {
+    let (pic, canister_id) = setup();
+    let id = add_described(&pic, canister_id, " Groceries ", " Milk and eggs ").unwrap();
+    let plain = add_todo(&pic, canister_id, "No notes").unwrap();
+    assert_eq!(
+        read_title(&pic, canister_id, id),
+        Ok("Groceries".to_owned())
+    );
+    assert_eq!(
+        read_description(&pic, canister_id, id),
+        Ok("Milk and eggs".to_owned())
+    );
+    assert_eq!(
+        read_description(&pic, canister_id, plain),
+        Ok(String::new())
+    );
+
+    assert_eq!(
+        update_description(&pic, canister_id, id, "Milk, eggs and bread"),
+        Ok("Milk and eggs".to_owned())
+    );
+    assert_eq!(
+        read_title(&pic, canister_id, id),
+        Ok("Groceries".to_owned())
+    );
+
+    assert_eq!(
+        update_todo(&pic, canister_id, id, "Shopping"),
+        Ok("Groceries".to_owned())
+    );
+    assert_eq!(
+        read_description(&pic, canister_id, id),
+        Ok("Milk, eggs and bread".to_owned())
+    );
+
+    assert_eq!(
+        update_description(&pic, canister_id, id, ""),
+        Ok("Milk, eggs and bread".to_owned())
+    );
+    assert_eq!(read_description(&pic, canister_id, id), Ok(String::new()));
+    assert_eq!(
+        update_description(&pic, canister_id, id, &"d".repeat(10_001)),
+        Err(TodoError::TooLong(10_000))
+    );
+    assert_eq!(
+        add_described(&pic, canister_id, "", "Only notes"),
+        Err(TodoError::EmptyContent)
+    );
+    assert_eq!(
+        update_description(&pic, canister_id, 99, "Nothing"),
+        Err(TodoError::NotFound(99))
+    );
+}
+
+#[test]
+fn test_init_args() {
+    let admin = Principal::from_slice(&[7]);
+    let (pic, canister_id) = setup_with(InitArgs {
+        default_page_size: 3,
+        admin,
+        ..init_args()
+    });
+    for i in 1..=5 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let page = read_page(&pic, canister_id, 1, 0).unwrap();
+    assert_eq!(page.items.len(), 3);
+    assert_eq!(page.total_pages, 2);
+    // an explicit page size still wins over the default
+    assert_eq!(read_page(&pic, canister_id, 1, 5).unwrap().items.len(), 5);
+
+    let denied: Result<u64, TodoError> =
+        call_update(&pic, canister_id, "clear_all", encode_one(()).unwrap());
+    assert_eq!(denied, Err(TodoError::Unauthorized));
+    assert_eq!(count(&pic, canister_id), 5);
+
+    // the configuration survives upgrades
+    pic.upgrade_canister(canister_id, load_todos_wasm(), vec![], None)
+        .expect("Failed to upgrade the canister");
+    assert_eq!(read_page(&pic, canister_id, 1, 0).unwrap().items.len(), 3);
+    let cleared: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin,
+        "clear_all",
+        encode_one(()).unwrap(),
+    );
+    assert_eq!(cleared, Ok(5));
+}
+
+#[test]
+fn test_get_config() {
+    let admin = Principal::from_slice(&[7]);
+    let (pic, canister_id) = setup_with(InitArgs {
+        default_page_size: 25,
+        admin,
+        done_ttl_nanos: Some(3600),
+        max_todos: Some(50),
+        max_adds_per_minute: None,
+    });
+    let get_config =
+        || -> Config { call_query(&pic, canister_id, "get_config", encode_one(()).unwrap()) };
+    assert_eq!(
+        get_config(),
+        Config {
+            default_page_size: 25,
+            admin: Some(admin),
+            done_ttl_nanos: 3600,
+            dedup: false,
+            max_todos: 50,
+            reuse_ids: false,
+            max_adds_per_minute: 100,
+            subscriber: None,
+            collapse_whitespace: false,
+        }
+    );
+
+    // changes by the admin show up
+    let result: Result<(), TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin,
+        "set_dedup",
+        encode_one(true).unwrap(),
+    );
+    assert_eq!(result, Ok(()));
+    assert!(get_config().dedup);
+}
+
+#[test]
+fn test_update_config() {
+    let (pic, canister_id) = setup();
+    for i in 1..=7 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let update_config = |sender: Principal, patch: ConfigPatch| -> Result<(), TodoError> {
+        call_update_as(
+            &pic,
+            canister_id,
+            sender,
+            "update_config",
+            encode_one(patch).unwrap(),
+        )
+    };
+    let get_config =
+        || -> Config { call_query(&pic, canister_id, "get_config", encode_one(()).unwrap()) };
+
+    let patch = ConfigPatch {
+        default_page_size: Some(3),
+        ..Default::default()
+    };
+    assert_eq!(
+        update_config(Principal::anonymous(), patch),
+        Err(TodoError::Unauthorized)
+    );
+    assert_eq!(get_config().default_page_size, 10);
+
+    // only the given fields change
+    let patch = ConfigPatch {
+        default_page_size: Some(3),
+        dedup: Some(true),
+        ..Default::default()
+    };
+    assert_eq!(update_config(admin(), patch), Ok(()));
+    let config = get_config();
+    assert_eq!(config.default_page_size, 3);
+    assert!(config.dedup);
+    assert_eq!(config.max_todos, 10_000);
+    let page = read_page(&pic, canister_id, 1, 0).unwrap();
+    assert_eq!(page.items.len(), 3);
+    assert_eq!(page.total_pages, 3);
+
+    // an invalid field rejects the whole patch
+    for size in [0, 101] {
+        let patch = ConfigPatch {
+            default_page_size: Some(size),
+            max_todos: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            update_config(admin(), patch),
+            Err(TodoError::InvalidPageSize(100))
+        );
+    }
+    let config = get_config();
+    assert_eq!(config.default_page_size, 3);
+    assert_eq!(config.max_todos, 10_000);
+}
+
+#[test]
+fn test_admin_gating() {
+    let (pic, canister_id) = setup();
+    let other = Principal::from_slice(&[3]);
+    add_todo(&pic, canister_id, "Anonymous todo").unwrap();
+    call_update_as::<Result<u64, TodoError>>(
+        &pic,
+        canister_id,
+        other,
+        "add",
+        encode_args(("Other todo", "")).unwrap(),
+    )
+    .unwrap();
+
+    for sender in [other, Principal::anonymous()] {
+        let denied: Result<u64, TodoError> = call_update_as(
+            &pic,
+            canister_id,
+            sender,
+            "clear_all",
+            encode_one(()).unwrap(),
+        );
+        assert_eq!(denied, Err(TodoError::Unauthorized));
+    }
+    assert_eq!(count(&pic, canister_id), 1);
+
+    // the admin was set at install time, so it cannot be replaced
+    for sender in [admin(), other, Principal::anonymous()] {
+        let replaced: Result<(), TodoError> = call_update_as(
+            &pic,
+            canister_id,
+            sender,
+            "set_admin",
+            encode_one(other).unwrap(),
+        );
+        assert_eq!(replaced, Err(TodoError::Unauthorized));
+    }
+
+    let cleared: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "clear_all",
+        encode_one(()).unwrap(),
+    );
+    assert_eq!(cleared, Ok(2));
+    assert_eq!(count(&pic, canister_id), 0);
+}
+
+#[test]
+fn test_stats() {
+    let (pic, canister_id) = setup();
+    let stats = |pic: &PocketIc| -> Stats {
+        call_query(pic, canister_id, "stats", encode_one(()).unwrap())
+    };
+    assert_eq!(
+        stats(&pic),
+        Stats {
+            total: 0,
+            done: 0,
+            pending: 0,
+            overdue: 0
+        }
+    );
+
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=6).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let now = ic_now(&pic);
+    for &id in &ids[..2] {
+        assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    }
+    // overdue but done, so it only counts as done
+    assert_eq!(set_due(&pic, canister_id, ids[0], Some(now - 1)), Ok(()));
+    assert_eq!(set_due(&pic, canister_id, ids[2], Some(now - 1)), Ok(()));
+    assert_eq!(set_due(&pic, canister_id, ids[3], Some(now - 1)), Ok(()));
+    assert_eq!(
+        set_due(&pic, canister_id, ids[4], Some(now + 3_600_000_000_000)),
+        Ok(())
+    );
+    // another principal's todos are not counted
+    call_update_as::<Result<u64, TodoError>>(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "add",
+        encode_args(("Someone else's", "")).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        stats(&pic),
+        Stats {
+            total: 6,
+            done: 2,
+            pending: 4,
+            overdue: 2
+        }
+    );
+}
+
+#[test]
+fn test_mark_all_done() {
+    let (pic, canister_id) = setup();
+    let stats = |pic: &PocketIc| -> Stats {
+        call_query(pic, canister_id, "stats", encode_one(()).unwrap())
+    };
+    let mark_all =
+        |method: &str| -> u64 { call_update(&pic, canister_id, method, encode_one(()).unwrap()) };
+    for i in 1..=5 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    set_done(&pic, canister_id, "mark_done", 2).unwrap();
+    let other = Principal::from_slice(&[3]);
+    let res: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        other,
+        "add",
+        encode_args(("Not mine", "")).unwrap(),
+    );
+    assert!(res.is_ok());
+
+    // only the todos that actually change are counted
+    assert_eq!(mark_all("mark_all_done"), 4);
+    assert_eq!(stats(&pic).done, 5);
+    assert_eq!(stats(&pic).pending, 0);
+    assert_eq!(mark_all("mark_all_done"), 0);
+    // other principals' todos are left alone
+    let todo: Result<Todo, TodoError> =
+        call_query_as(&pic, canister_id, other, "read", encode_one(6u64).unwrap());
+    assert!(!todo.unwrap().done);
+
+    assert_eq!(mark_all("mark_all_undone"), 5);
+    assert_eq!(stats(&pic).done, 0);
+    assert_eq!(stats(&pic).pending, 5);
+    assert_eq!(mark_all("mark_all_undone"), 0);
+}
+
+#[test]
+fn test_reorder() {
+    let (pic, canister_id) = setup();
+    add_many(
+        &pic,
+        canister_id,
+        (1..=5).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    assert_eq!(ordered_ids(&pic, canister_id), vec![1, 2, 3, 4, 5]);
+
+    // the middle todo moves to the front
+    assert_eq!(reorder(&pic, canister_id, 3, 0), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![3, 1, 2, 4, 5]);
+
+    assert_eq!(reorder(&pic, canister_id, 1, 3), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![3, 2, 4, 1, 5]);
+
+    // positions past the end move the todo to the end
+    assert_eq!(reorder(&pic, canister_id, 3, 100), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![2, 4, 1, 5, 3]);
+
+    // new todos are appended after the reordered ones
+    let added = add_todo(&pic, canister_id, "Todo 6").unwrap();
+    assert_eq!(ordered_ids(&pic, canister_id), vec![2, 4, 1, 5, 3, added]);
+
+    assert_eq!(
+        reorder(&pic, canister_id, 99, 0),
+        Err(TodoError::NotFound(99))
+    );
+    // read_all keeps ordering by ID
+    let (todos, _) = read_all(&pic, canister_id, 1, 10).unwrap();
+    let ids: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(ids, vec![1, 2, 3, 4, 5, added]);
+}
+
+#[test]
+fn test_purge_done() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=6).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    for &id in &[ids[0], ids[2], ids[3]] {
+        assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    }
+    let other = Principal::from_slice(&[3]);
+    let foreign: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        other,
+        "add",
+        encode_args(("Foreign", "")).unwrap(),
+    );
+    let foreign = foreign.unwrap();
+    let done: Result<Option<u64>, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        other,
+        "mark_done",
+        encode_one(foreign).unwrap(),
+    );
+    assert_eq!(done, Ok(None));
+
+    let purge = || -> u64 { call_update(&pic, canister_id, "purge_done", encode_one(()).unwrap()) };
+    assert_eq!(purge(), 3);
+    let (todos, _) = read_all(&pic, canister_id, 1, 10).unwrap();
+    let remaining: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(remaining, vec![ids[1], ids[4], ids[5]]);
+    assert!(todos.iter().all(|todo| !todo.done));
+    // nothing left to purge
+    assert_eq!(purge(), 0);
+
+    // purged todos end up in the trash
+    assert!(undo_delete(&pic, canister_id, ids[2]).unwrap().done);
+    // other principals keep their done todos
+    let foreign_todo: Result<Todo, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        other,
+        "read",
+        encode_one(foreign).unwrap(),
+    );
+    assert!(foreign_todo.is_ok());
+}
+
+#[test]
+fn test_swap() {
+    let (pic, canister_id) = setup();
+    add_many(
+        &pic,
+        canister_id,
+        (1..=4).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let swap = |a: u64, b: u64| -> Result<(), TodoError> {
+        call_update(&pic, canister_id, "swap", encode_args((a, b)).unwrap())
+    };
+
+    assert_eq!(swap(2, 3), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![1, 3, 2, 4]);
+    assert_eq!(swap(4, 1), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![4, 3, 2, 1]);
+    // swaps work on top of reordering
+    assert_eq!(reorder(&pic, canister_id, 1, 0), Ok(()));
+    assert_eq!(swap(1, 2), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![2, 4, 3, 1]);
+
+    let version = read_todo(&pic, canister_id, 3).unwrap().version;
+    assert_eq!(swap(3, 3), Ok(()));
+    assert_eq!(read_todo(&pic, canister_id, 3).unwrap().version, version);
+    assert_eq!(swap(3, 99), Err(TodoError::NotFound(99)));
+    assert_eq!(swap(99, 3), Err(TodoError::NotFound(99)));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![2, 4, 3, 1]);
+}
+
+#[test]
+fn test_move_to_top_and_bottom() {
+    let (pic, canister_id) = setup();
+    add_many(
+        &pic,
+        canister_id,
+        (1..=5).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let move_to = |method: &str, id: u64| -> Result<(), TodoError> {
+        call_update(&pic, canister_id, method, encode_one(id).unwrap())
+    };
+
+    assert_eq!(move_to("move_to_top", 3), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![3, 1, 2, 4, 5]);
+    assert_eq!(move_to("move_to_bottom", 2), Ok(()));
+    assert_eq!(ordered_ids(&pic, canister_id), vec![3, 1, 4, 5, 2]);
+
+    // already at the extremes, so nothing changes
+    let version = read_todo(&pic, canister_id, 3).unwrap().version;
+    assert_eq!(move_to("move_to_top", 3), Ok(()));
+    assert_eq!(read_todo(&pic, canister_id, 3).unwrap().version, version);
+    let version = read_todo(&pic, canister_id, 2).unwrap().version;
+    assert_eq!(move_to("move_to_bottom", 2), Ok(()));
+    assert_eq!(read_todo(&pic, canister_id, 2).unwrap().version, version);
+    assert_eq!(ordered_ids(&pic, canister_id), vec![3, 1, 4, 5, 2]);
+
+    assert_eq!(move_to("move_to_top", 99), Err(TodoError::NotFound(99)));
+    assert_eq!(move_to("move_to_bottom", 99), Err(TodoError::NotFound(99)));
+}
+
+#[test]
+fn test_pin() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=15).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let page_ids = |pic: &PocketIc, page| -> Vec<u64> {
+        let (todos, _) = read_all(pic, canister_id, page, 10).unwrap();
+        todos.iter().map(|todo| todo.id).collect()
+    };
+
+    assert_eq!(set_pinned(&pic, canister_id, true, ids[14]), Ok(()));
+    assert_eq!(page_ids(&pic, 1)[0], ids[14]);
+    assert_eq!(page_ids(&pic, 1)[1..], ids[..9]);
+    assert_eq!(page_ids(&pic, 2), ids[9..14]);
+
+    // pins survive content changes
+    update_todo(&pic, canister_id, ids[14], "Still pinned").unwrap();
+    assert!(read_todo(&pic, canister_id, ids[14]).unwrap().pinned);
+
+    // pinned todos are ordered by ID among themselves
+    assert_eq!(set_pinned(&pic, canister_id, true, ids[11]), Ok(()));
+    assert_eq!(page_ids(&pic, 1)[..3], [ids[11], ids[14], ids[0]]);
+
+    assert_eq!(set_pinned(&pic, canister_id, false, ids[14]), Ok(()));
+    assert_eq!(page_ids(&pic, 1)[..2], [ids[11], ids[0]]);
+    assert_eq!(
+        set_pinned(&pic, canister_id, true, 99),
+        Err(TodoError::NotFound(99))
+    );
+}
+
+#[test]
+fn test_star() {
+    let (pic, canister_id) = setup();
+    for i in 1..=4 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let favorites = || -> Vec<u64> {
+        let todos: Vec<Todo> = call_query(&pic, canister_id, "favorites", encode_one(()).unwrap());
+        todos.iter().map(|todo| todo.id).collect()
+    };
+    assert!(favorites().is_empty());
+
+    assert_eq!(set_starred(&pic, canister_id, true, 3), Ok(()));
+    assert_eq!(set_starred(&pic, canister_id, true, 1), Ok(()));
+    assert_eq!(favorites(), vec![1, 3]);
+    // starring doesn't change the order of read_all
+    let (todos, _) = read_all(&pic, canister_id, 1, 10).unwrap();
+    let ids: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(ids, vec![1, 2, 3, 4]);
+
+    // stars survive content changes
+    update_todo(&pic, canister_id, 3, "Still starred").unwrap();
+    assert!(read_todo(&pic, canister_id, 3).unwrap().starred);
+
+    assert_eq!(set_starred(&pic, canister_id, false, 1), Ok(()));
+    assert_eq!(favorites(), vec![3]);
+    assert_eq!(
+        set_starred(&pic, canister_id, true, 99),
+        Err(TodoError::NotFound(99))
+    );
+    // favorites are per principal
+    let other: Vec<Todo> = call_query_as(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "favorites",
+        encode_one(()).unwrap(),
+    );
+    assert!(other.is_empty());
+}
+
+#[test]
+fn test_color() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Colorful").unwrap();
+    let set_color = |id: u64, color: Option<&str>| -> Result<(), TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "set_color",
+            encode_args((id, color)).unwrap(),
+        )
+    };
+    let color = || read_todo(&pic, canister_id, id).unwrap().color;
+    assert_eq!(color(), None);
+
+    assert_eq!(set_color(id, Some("#1a2B3c")), Ok(()));
+    assert_eq!(color(), Some("#1a2B3c".to_owned()));
+    for invalid in ["1a2b3c", "#1a2b3", "#1a2b3c4", "#1g2b3c", "red", ""] {
+        assert_eq!(set_color(id, Some(invalid)), Err(TodoError::InvalidColor));
+    }
+    // invalid colors leave the old one in place
+    assert_eq!(color(), Some("#1a2B3c".to_owned()));
+    assert_eq!(set_color(id, None), Ok(()));
+    assert_eq!(color(), None);
+    assert_eq!(set_color(99, None), Err(TodoError::NotFound(99)));
+}
+
+#[test]
+fn test_attachments() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "With a file").unwrap();
+    let set_attachment = |id: u64, bytes: Vec<u8>| -> Result<(), TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "set_attachment",
+            encode_args((id, bytes)).unwrap(),
+        )
+    };
+    let clear_attachment = |id: u64| -> Result<(), TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "clear_attachment",
+            encode_one(id).unwrap(),
+        )
+    };
+    let get_attachment = |sender: Principal, id: u64| -> Option<Vec<u8>> {
+        call_query_as(
+            &pic,
+            canister_id,
+            sender,
+            "get_attachment",
+            encode_one(id).unwrap(),
+        )
+    };
+    let me = Principal::anonymous();
+    assert_eq!(get_attachment(me, id), None);
+
+    let image = vec![0x89, b'P', b'N', b'G', 0, 1, 2, 3];
+    assert_eq!(set_attachment(id, image.clone()), Ok(()));
+    assert_eq!(get_attachment(me, id), Some(image));
+    assert_eq!(get_attachment(Principal::from_slice(&[3]), id), None);
+
+    // 1 MiB is fine, one byte more is not
+    let max = 1024 * 1024;
+    assert_eq!(set_attachment(id, vec![7; max]), Ok(()));
+    assert_eq!(get_attachment(me, id).map(|bytes| bytes.len()), Some(max));
+    assert_eq!(
+        set_attachment(id, vec![7; max + 1]),
+        Err(TodoError::TooLarge(max))
+    );
+    assert_eq!(get_attachment(me, id).map(|bytes| bytes.len()), Some(max));
+
+    assert_eq!(clear_attachment(id), Ok(()));
+    assert_eq!(get_attachment(me, id), None);
+    assert_eq!(set_attachment(99, vec![1]), Err(TodoError::NotFound(99)));
+    assert_eq!(clear_attachment(99), Err(TodoError::NotFound(99)));
+
+    // deleting the todo deletes the attachment
+    assert_eq!(set_attachment(id, vec![1, 2, 3]), Ok(()));
+    delete_todo(&pic, canister_id, id).unwrap();
+    undo_delete(&pic, canister_id, id).unwrap();
+    assert_eq!(get_attachment(me, id), None);
+}
+
+#[test]
+fn test_export_json() {
+    let (pic, canister_id) = setup();
+    let export = |pic: &PocketIc| -> String {
+        call_query(pic, canister_id, "export_json", encode_one(()).unwrap())
+    };
+    assert_eq!(export(&pic), "[]");
+
+    let first = add_described(&pic, canister_id, "First", "With \"quotes\"").unwrap();
+    let second = add_todo(&pic, canister_id, "Second").unwrap();
+    assert_eq!(set_done(&pic, canister_id, "mark_done", second), Ok(()));
+
+    let exported: Vec<Todo> = serde_json::from_str(&export(&pic)).expect("Invalid JSON");
+    assert_eq!(
+        exported,
+        vec![
+            read_todo(&pic, canister_id, first).unwrap(),
+            read_todo(&pic, canister_id, second).unwrap(),
+        ]
+    );
+    assert_eq!(exported[0].description, "With \"quotes\"");
+    assert_eq!(exported[0].owner, Principal::anonymous());
+    assert!(exported[1].done);
+}
+
+#[test]
+fn test_export_json_ids() {
+    let (pic, canister_id) = setup();
+    let export = |ids: Vec<u64>| -> Vec<Todo> {
+        let json: String = call_query(
+            &pic,
+            canister_id,
+            "export_json_ids",
+            encode_one(ids).unwrap(),
+        );
+        serde_json::from_str(&json).expect("Invalid JSON")
+    };
+    let first = add_todo(&pic, canister_id, "First").unwrap();
+    add_todo(&pic, canister_id, "Second").unwrap();
+    let third = add_todo(&pic, canister_id, "Third").unwrap();
+
+    let exported = export(vec![third, 99, first, third]);
+    assert_eq!(
+        exported,
+        vec![
+            read_todo(&pic, canister_id, third).unwrap(),
+            read_todo(&pic, canister_id, first).unwrap(),
+        ]
+    );
+    assert!(export(vec![]).is_empty());
+}
+
+#[test]
+fn test_import_json() {
+    let (pic, canister_id) = setup();
+    let existing = add_todo(&pic, canister_id, "Already here").unwrap();
+    let json = r#"[
+        {"id": 1, "title": "Restored", "description": "From a backup", "done": true,
+         "priority": "High", "tags": ["Home", "home"], "created_at": 42},
+        {"title": "Minimal"}
+    ]"#;
+    assert_eq!(import_json(&pic, canister_id, json), Ok(2));
+
+    // the imported ID 1 did not overwrite the existing todo
+    assert_eq!(
+        read_title(&pic, canister_id, existing),
+        Ok("Already here".to_owned())
+    );
+    let (todos, _) = read_all(&pic, canister_id, 1, 10).unwrap();
+    assert_eq!(todos.len(), 3);
+    let restored = &todos[1];
+    assert!(restored.id > existing);
+    assert_eq!(restored.title, "Restored");
+    assert_eq!(restored.description, "From a backup");
+    assert!(restored.done);
+    assert_eq!(restored.priority, Priority::High);
+    assert_eq!(restored.tags, vec!["home".to_string()]);
+    assert_eq!(restored.created_at, 42);
+    assert_eq!(todos[2].title, "Minimal");
+    assert_eq!(todos[2].priority, Priority::Medium);
+
+    // an export can be imported again
+    let exported: String = call_query(&pic, canister_id, "export_json", encode_one(()).unwrap());
+    assert_eq!(import_json(&pic, canister_id, &exported), Ok(3));
+    assert_eq!(count(&pic, canister_id), 6);
+}
+
+#[test]
+fn test_import_preserving_ids() {
+    let (pic, canister_id) = setup();
+    let import = |json: &str, overwrite: bool| -> Result<u64, TodoError> {
+        call_update(
+            &pic,
+            canister_id,
+            "import_preserving_ids",
+            encode_args((json, overwrite)).unwrap(),
+        )
+    };
+
+    // a clean import keeps the IDs and moves the counter past them
+    let json = r#"[{"id": 7, "title": "Seven", "done": true}, {"id": 3, "title": "Three"}]"#;
+    assert_eq!(import(json, false), Ok(2));
+    assert_eq!(read_title(&pic, canister_id, 7), Ok("Seven".to_owned()));
+    assert!(read_todo(&pic, canister_id, 7).unwrap().done);
+    assert_eq!(read_title(&pic, canister_id, 3), Ok("Three".to_owned()));
+    assert_eq!(add_todo(&pic, canister_id, "Fresh"), Ok(8));
+
+    // a collision fails without overwrite and leaves everything as it was
+    let json = r#"[{"id": 9, "title": "Nine"}, {"id": 3, "title": "Replaced"}]"#;
+    assert_eq!(
+        import(json, false),
+        Err(TodoError::InvalidEntry {
+            index: 1,
+            error: Box::new(TodoError::IdTaken(3))
+        })
+    );
+    assert_eq!(read_title(&pic, canister_id, 3), Ok("Three".to_owned()));
+    assert!(read_todo(&pic, canister_id, 9).is_err());
+
+    // with overwrite the todo is replaced, and new IDs are still added
+    let before = read_todo(&pic, canister_id, 3).unwrap();
+    assert_eq!(import(json, true), Ok(2));
+    let replaced = read_todo(&pic, canister_id, 3).unwrap();
+    assert_eq!(replaced.title, "Replaced");
+    assert_eq!(replaced.order, before.order);
+    assert!(replaced.version > before.version);
+    assert_eq!(read_title(&pic, canister_id, 9), Ok("Nine".to_owned()));
+    assert_eq!(count(&pic, canister_id), 5);
+
+    // the same ID twice in one batch, or another principal's todo, is never overwritten
+    assert_eq!(
+        import(
+            r#"[{"id": 20, "title": "A"}, {"id": 20, "title": "B"}]"#,
+            true
+        ),
+        Err(TodoError::InvalidEntry {
+            index: 1,
+            error: Box::new(TodoError::IdTaken(20))
+        })
+    );
+    let stranger = Principal::from_slice(&[42]);
+    let result: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        stranger,
+        "import_preserving_ids",
+        encode_args((r#"[{"id": 7, "title": "Mine now"}]"#, true)).unwrap(),
+    );
+    assert_eq!(
+        result,
+        Err(TodoError::InvalidEntry {
+            index: 0,
+            error: Box::new(TodoError::IdTaken(7))
+        })
+    );
+    assert_eq!(read_title(&pic, canister_id, 7), Ok("Seven".to_owned()));
+}
+
+#[test]
+fn test_import_json_rejects_invalid_input() {
+    let (pic, canister_id) = setup();
+    for json in [
+        "",
+        "[{\"title\": \"Unclosed\"",
+        "{\"title\": \"Not an array\"}",
+        "[{}]",
+    ] {
+        assert!(
+            matches!(
+                import_json(&pic, canister_id, json),
+                Err(TodoError::InvalidJson(message)) if !message.is_empty()
+            ),
+            "{:?} was accepted",
+            json
+        );
+    }
+    assert_eq!(
+        import_json(&pic, canister_id, r#"[{"title": "Fine"}, {"title": " "}]"#),
+        Err(TodoError::InvalidEntry {
+            index: 1,
+            error: Box::new(TodoError::EmptyContent)
+        })
+    );
+    assert_eq!(count(&pic, canister_id), 0);
+}
+
+#[test]
+fn test_export_csv() {
+    let (pic, canister_id) = setup();
+    let export = |pic: &PocketIc| -> String {
+        call_query(pic, canister_id, "export_csv", encode_one(()).unwrap())
+    };
+    assert_eq!(export(&pic), "id,title,done,created_at\r\n");
+
+    let plain = add_todo(&pic, canister_id, "Plain").unwrap();
+    let tricky = add_todo(&pic, canister_id, r#"Milk, "organic""#).unwrap();
+    let multiline = add_todo(&pic, canister_id, "Line one\nLine two").unwrap();
+    assert_eq!(set_done(&pic, canister_id, "mark_done", plain), Ok(()));
+
+    let created_at = |id| read_todo(&pic, canister_id, id).unwrap().created_at;
+    assert_eq!(
+        export(&pic),
+        format!(
+            "id,title,done,created_at\r\n\
+             {},Plain,true,{}\r\n\
+             {},\"Milk, \"\"organic\"\"\",false,{}\r\n\
+             {},\"Line one\nLine two\",false,{}\r\n",
+            plain,
+            created_at(plain),
+            tricky,
+            created_at(tricky),
+            multiline,
+            created_at(multiline),
+        )
+    );
+}
+
+#[test]
+fn test_metrics() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=3).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    assert_eq!(set_done(&pic, canister_id, "mark_done", ids[0]), Ok(()));
+
+    let response = http_get(&pic, canister_id, "/metrics");
+    assert_eq!(response.status_code, 200);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("# TYPE todo_total gauge\ntodo_total 3\n"));
+    assert!(body.contains("\ntodo_done 1\n"));
+    let stable_memory_bytes: u64 = body
+        .lines()
+        .find_map(|line| line.strip_prefix("stable_memory_bytes "))
+        .expect("stable_memory_bytes is missing")
+        .parse()
+        .unwrap();
+    assert!(stable_memory_bytes > 0);
+
+    assert_eq!(http_get(&pic, canister_id, "/metrics?x=1").status_code, 200);
+    assert_eq!(http_get(&pic, canister_id, "/").status_code, 404);
+    assert_eq!(
+        http_get(&pic, canister_id, "/metrics/extra").status_code,
+        404
+    );
+}
+
+#[test]
+fn test_cycles_balance() {
+    let (pic, canister_id) = setup();
+    let balance = |pic: &PocketIc| -> u64 {
+        call_query(pic, canister_id, "cycles_balance", encode_one(()).unwrap())
+    };
+    // `setup` adds 2T cycles, installing the canister only burns a small part of them
+    let initial = balance(&pic);
+    assert!(initial > 1_900_000_000_000 && initial <= 2_000_000_000_000);
+
+    pic.add_cycles(canister_id, 1_000_000_000_000);
+    let topped_up = balance(&pic);
+    assert!(topped_up > initial + 900_000_000_000 && topped_up <= initial + 1_000_000_000_000);
+}
+
+#[test]
+fn test_done_todos_are_swept() {
+    let (pic, canister_id) = setup();
+    let old_done = add_todo(&pic, canister_id, "Done long ago").unwrap();
+    let old_pending = add_todo(&pic, canister_id, "Pending forever").unwrap();
+    let subtask = add_subtask(&pic, canister_id, old_done, "Part of it").unwrap();
+    assert_eq!(set_done(&pic, canister_id, "mark_done", old_done), Ok(()));
+
+    pic.advance_time(Duration::from_secs(29 * 24 * 3600));
+    let recent_done = add_todo(&pic, canister_id, "Done recently").unwrap();
+    assert_eq!(
+        set_done(&pic, canister_id, "mark_done", recent_done),
+        Ok(())
+    );
+
+    // 31 days after the first todo was done, 2 days after the recent one
+    pic.advance_time(Duration::from_secs(2 * 24 * 3600));
+    for _ in 0..5 {
+        pic.tick();
+    }
+    assert_eq!(
+        read_todo(&pic, canister_id, old_done),
+        Err(TodoError::NotFound(old_done))
+    );
+    assert!(read_todo(&pic, canister_id, old_pending).is_ok());
+    assert!(read_todo(&pic, canister_id, recent_done).is_ok());
+    // the subtask went with its parent, both into the trash
+    assert_eq!(
+        read_todo(&pic, canister_id, subtask),
+        Err(TodoError::NotFound(subtask))
+    );
+    assert!(undo_delete(&pic, canister_id, old_done).is_ok());
+    assert!(undo_delete(&pic, canister_id, subtask).is_ok());
+    assert_eq!(children_ids(&pic, canister_id, old_done), vec![subtask]);
+    delete_todo(&pic, canister_id, old_done).unwrap();
+
+    // a shorter TTL takes effect with the next daily sweep
+    let shortened: Result<(), TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "set_done_ttl",
+        encode_one(3_600_000_000_000u64).unwrap(),
+    );
+    assert_eq!(shortened, Ok(()));
+    pic.advance_time(Duration::from_secs(24 * 3600));
+    for _ in 0..5 {
+        pic.tick();
+    }
+    assert_eq!(
+        read_todo(&pic, canister_id, recent_done),
+        Err(TodoError::NotFound(recent_done))
+    );
+    assert!(read_todo(&pic, canister_id, old_pending).is_ok());
+
+    let denied: Result<(), TodoError> =
+        call_update(&pic, canister_id, "set_done_ttl", encode_one(0u64).unwrap());
+    assert_eq!(denied, Err(TodoError::Unauthorized));
+}
+
+#[test]
+fn test_update_version_conflict() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Original").unwrap();
+    let read = read_todo(&pic, canister_id, id).unwrap();
+    assert_eq!(read.version, 0);
+
+    // two clients read version 0, the first one to write wins
+    assert_eq!(
+        update_todo_at(&pic, canister_id, id, "First client", read.version),
+        Ok("Original".to_owned())
+    );
+    assert_eq!(
+        update_todo_at(&pic, canister_id, id, "Second client", read.version),
+        Err(TodoError::VersionConflict { current: 1 })
+    );
+    assert_eq!(
+        read_title(&pic, canister_id, id),
+        Ok("First client".to_owned())
+    );
+
+    // every other change bumps the version as well
+    assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    assert_eq!(set_priority(&pic, canister_id, id, Priority::High), Ok(()));
+    let current = read_todo(&pic, canister_id, id).unwrap().version;
+    assert_eq!(current, 3);
+
+    // the second client resubmits based on the current version
+    assert_eq!(
+        update_todo_at(&pic, canister_id, id, "Second client", current),
+        Ok("First client".to_owned())
+    );
+    assert_eq!(read_todo(&pic, canister_id, id).unwrap().version, 4);
+    assert_eq!(
+        update_todo_at(&pic, canister_id, 99, "Missing", 0),
+        Err(TodoError::NotFound(99))
+    );
+}
+
+#[test]
+fn test_filter() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=6).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    for &id in &ids[..4] {
+        assert_eq!(edit_tag(&pic, canister_id, "add_tag", id, "work"), Ok(()));
+    }
+    for &id in [ids[0], ids[2], ids[4]].iter() {
+        assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    }
+    let filter_ids = |done: Option<bool>, tag: Option<&str>| -> Vec<u64> {
+        let result: Result<(Vec<Todo>, Option<u16>), TodoError> = call_query(
+            &pic,
+            canister_id,
+            "filter",
+            encode_args((done, tag, 1u16)).unwrap(),
+        );
+        match result {
+            Ok((todos, _)) => todos.iter().map(|todo| todo.id).collect(),
+            Err(TodoError::InvalidPage(1)) => vec![],
+            Err(error) => panic!("Unexpected error: {:?}", error),
+        }
+    };
+
+    assert_eq!(filter_ids(Some(false), Some("Work")), vec![ids[1], ids[3]]);
+    assert_eq!(filter_ids(Some(true), Some("work")), vec![ids[0], ids[2]]);
+    assert_eq!(filter_ids(Some(false), None), vec![ids[1], ids[3], ids[5]]);
+    assert_eq!(filter_ids(None, Some("work")), ids[..4]);
+    assert_eq!(filter_ids(None, None), ids);
+    assert!(filter_ids(Some(true), Some("home")).is_empty());
+}
+
+#[test]
+fn test_filter_includes_shared_todos() {
+    let (pic, canister_id) = setup();
+    let own = add_todo(&pic, canister_id, "Own").unwrap();
+    let friend = Principal::from_slice(&[1]);
+    let shared = add_as(&pic, canister_id, friend, "Shared").unwrap();
+    let result: Result<(), TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        friend,
+        "share",
+        encode_args((shared, Principal::anonymous())).unwrap(),
+    );
+    assert_eq!(result, Ok(()));
+    let result: Result<Option<u64>, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        friend,
+        "mark_done",
+        encode_one(shared).unwrap(),
+    );
+    assert_eq!(result, Ok(None));
+    let ids = |method: &str, args: Vec<u8>| -> Vec<u64> {
+        let result: Result<(Vec<Todo>, Option<u16>), TodoError> =
+            call_query(&pic, canister_id, method, args);
+        result.unwrap().0.iter().map(|todo| todo.id).collect()
+    };
+
+    // like read_all, which lists the shared todo too
+    let page = read_page(&pic, canister_id, 1, 0).unwrap();
+    let read_all_ids: Vec<u64> = page.items.iter().map(|todo| todo.id).collect();
+    assert_eq!(read_all_ids, vec![own, shared]);
+    let filter = |done: Option<bool>| encode_args((done, None::<String>, 1u16)).unwrap();
+    assert_eq!(ids("filter", filter(None)), read_all_ids);
+    assert_eq!(ids("filter", filter(Some(true))), vec![shared]);
+    assert_eq!(ids("filter", filter(Some(false))), vec![own]);
+    assert_eq!(ids("get_done", encode_one(1u16).unwrap()), vec![shared]);
+    assert_eq!(ids("get_pending", encode_one(1u16).unwrap()), vec![own]);
+}
+
+#[test]
+fn test_get_done_and_pending() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=15).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    // every third todo is done
+    for &id in ids.iter().step_by(3) {
+        assert_eq!(set_done(&pic, canister_id, "mark_done", id), Ok(()));
+    }
+    let query = |method: &str, page: u16| -> Result<(Vec<u64>, Option<u16>), TodoError> {
+        let res: Result<(Vec<Todo>, Option<u16>), TodoError> =
+            call_query(&pic, canister_id, method, encode_one(page).unwrap());
+        res.map(|(todos, next_page)| (todos.iter().map(|todo| todo.id).collect(), next_page))
+    };
+
+    let done: Vec<u64> = ids.iter().step_by(3).copied().collect();
+    assert_eq!(query("get_done", 1), Ok((done, None)));
+    assert_eq!(query("get_done", 2), Err(TodoError::InvalidPage(2)));
+
+    let pending: Vec<u64> = ids.iter().copied().filter(|id| (id - 1) % 3 != 0).collect();
+    assert_eq!(pending.len(), 10);
+    assert_eq!(query("get_pending", 1), Ok((pending[..10].to_vec(), None)));
+    assert_eq!(set_done(&pic, canister_id, "mark_undone", ids[0]), Ok(()));
+    let (first_page, next_page) = query("get_pending", 1).unwrap();
+    assert_eq!(first_page[0], ids[0]);
+    assert_eq!(next_page, Some(2));
+    assert_eq!(query("get_pending", 2), Ok((vec![ids[14]], None)));
+}
+
+#[test]
+fn test_read_all_sort() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        ["banana", "Cherry", "apple", "Banana split"]
+            .iter()
+            .map(|title| title.to_string())
+            .collect(),
+    )
+    .unwrap();
+    let sorted = |sort: SortBy| -> Vec<String> {
+        let page: Result<Page, TodoError> = call_query(
+            &pic,
+            canister_id,
+            "read_all",
+            encode_args((1u16, 10u16, Some(sort))).unwrap(),
+        );
+        page.unwrap()
+            .items
+            .into_iter()
+            .map(|todo| todo.title)
+            .collect()
+    };
+
+    assert_eq!(
+        sorted(SortBy::IdDesc),
+        vec!["Banana split", "apple", "Cherry", "banana"]
+    );
+    assert_eq!(
+        sorted(SortBy::TitleAsc),
+        vec!["apple", "banana", "Banana split", "Cherry"]
+    );
+    // without a sort the todos stay ordered by ID
+    let (todos, _) = read_all(&pic, canister_id, 1, 10).unwrap();
+    let default_ids: Vec<u64> = todos.iter().map(|todo| todo.id).collect();
+    assert_eq!(default_ids, ids);
+}
+
+#[test]
+fn test_subtasks() {
+    let (pic, canister_id) = setup();
+    let parent = add_todo(&pic, canister_id, "Move house").unwrap();
+    let other = add_todo(&pic, canister_id, "Unrelated").unwrap();
+    let boxes = add_subtask(&pic, canister_id, parent, "Buy boxes").unwrap();
+    let van = add_subtask(&pic, canister_id, parent, "Rent a van").unwrap();
+    let tape = add_subtask(&pic, canister_id, boxes, "Get tape").unwrap();
+    assert_eq!(
+        add_subtask(&pic, canister_id, 99, "Orphan"),
+        Err(TodoError::NotFound(99))
+    );
+    assert_eq!(
+        add_subtask(&pic, canister_id, parent, " "),
+        Err(TodoError::EmptyContent)
+    );
+
+    assert_eq!(
+        read_todo(&pic, canister_id, boxes).unwrap().parent_id,
+        Some(parent)
+    );
+    assert_eq!(
+        read_todo(&pic, canister_id, parent).unwrap().parent_id,
+        None
+    );
+    assert_eq!(children_ids(&pic, canister_id, parent), vec![boxes, van]);
+    assert_eq!(children_ids(&pic, canister_id, boxes), vec![tape]);
+    assert!(children_ids(&pic, canister_id, other).is_empty());
+
+    // deleting the parent deletes all of its subtasks, also nested ones
+    delete_todo(&pic, canister_id, parent).unwrap();
+    for id in [parent, boxes, van, tape] {
+        assert_eq!(
+            read_todo(&pic, canister_id, id),
+            Err(TodoError::NotFound(id))
+        );
+    }
+    assert!(read_todo(&pic, canister_id, other).is_ok());
+    assert_eq!(
+        undo_delete(&pic, canister_id, van).unwrap().parent_id,
+        Some(parent)
+    );
+
+    let list = add_todo(&pic, canister_id, "List").unwrap();
+    let item = add_subtask(&pic, canister_id, list, "Item").unwrap();
+    let deleted: Vec<u64> = call_update(
+        &pic,
+        canister_id,
+        "delete_many",
+        encode_one(vec![list, item]).unwrap(),
+    );
+    // the subtask was already gone with its parent
+    assert_eq!(deleted, vec![list]);
+    assert_eq!(
+        read_todo(&pic, canister_id, item),
+        Err(TodoError::NotFound(item))
+    );
+}
+
+#[test]
+fn test_duplicate() {
+    let (pic, canister_id) = setup();
+    let original = add_described(&pic, canister_id, "Weekly report", "Send to team").unwrap();
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", original, "work"),
+        Ok(())
+    );
+    assert_eq!(
+        set_priority(&pic, canister_id, original, Priority::High),
+        Ok(())
+    );
+    assert_eq!(set_done(&pic, canister_id, "mark_done", original), Ok(()));
+    assert_eq!(set_pinned(&pic, canister_id, true, original), Ok(()));
+
+    pic.advance_time(Duration::from_secs(60));
+    let duplicate = |id: u64| -> Result<u64, TodoError> {
+        call_update(&pic, canister_id, "duplicate", encode_one(id).unwrap())
+    };
+    let copy_id = duplicate(original).unwrap();
+    assert_ne!(copy_id, original);
+    let before = read_todo(&pic, canister_id, original).unwrap();
+    let copy = read_todo(&pic, canister_id, copy_id).unwrap();
+    assert_eq!(copy.title, "Weekly report");
+    assert_eq!(copy.description, "Send to team");
+    assert_eq!(copy.tags, vec!["work".to_string()]);
+    assert_eq!(copy.priority, Priority::High);
+    assert!(!copy.done);
+    assert!(!copy.pinned);
+    assert!(copy.created_at > before.created_at);
+    assert_eq!(copy.created_at, copy.updated_at);
+
+    // changing the copy leaves the original alone
+    update_todo(&pic, canister_id, copy_id, "Monthly report").unwrap();
+    assert_eq!(
+        edit_tag(&pic, canister_id, "remove_tag", copy_id, "work"),
+        Ok(())
+    );
+    assert_eq!(read_todo(&pic, canister_id, original).unwrap(), before);
+
+    assert_eq!(duplicate(99), Err(TodoError::NotFound(99)));
+}
+
+#[test]
+fn test_dedup_mode() {
+    let (pic, canister_id) = setup();
+    let set_dedup = |sender: Principal, enabled: bool| -> Result<(), TodoError> {
+        call_update_as(
+            &pic,
+            canister_id,
+            sender,
+            "set_dedup",
+            encode_one(enabled).unwrap(),
+        )
+    };
+
+    // off by default, identical titles are fine
+    add_todo(&pic, canister_id, "Water plants").unwrap();
+    add_todo(&pic, canister_id, "Water plants").unwrap();
+
+    assert_eq!(
+        set_dedup(Principal::anonymous(), true),
+        Err(TodoError::Unauthorized)
+    );
+    assert_eq!(set_dedup(admin(), true), Ok(()));
+    assert_eq!(
+        add_todo(&pic, canister_id, "  Water plants "),
+        Err(TodoError::Duplicate)
+    );
+    // titles are compared exactly after trimming
+    add_todo(&pic, canister_id, "water plants").unwrap();
+    assert_eq!(
+        add_many(
+            &pic,
+            canister_id,
+            vec!["New".to_owned(), "Other".to_owned(), "New".to_owned()]
+        ),
+        Err(TodoError::InvalidEntry {
+            index: 2,
+            error: Box::new(TodoError::Duplicate)
+        })
+    );
+    // dedup is per principal
+    let other: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "add",
+        encode_args(("Water plants", "")).unwrap(),
+    );
+    assert!(other.is_ok());
+    assert_eq!(count(&pic, canister_id), 3);
+
+    assert_eq!(set_dedup(admin(), false), Ok(()));
+    add_todo(&pic, canister_id, "Water plants").unwrap();
+    assert_eq!(count(&pic, canister_id), 4);
+}
+
+#[test]
+fn test_id_reuse_off() {
+    let (pic, canister_id) = setup();
+    for id in 1..=3 {
+        assert_eq!(add_todo(&pic, canister_id, &format!("Todo {}", id)), Ok(id));
+    }
+    delete_todo(&pic, canister_id, 2).unwrap();
+    delete_todo(&pic, canister_id, 1).unwrap();
+
+    // by default IDs are never handed out twice
+    assert_eq!(add_todo(&pic, canister_id, "Next"), Ok(4));
+    assert_eq!(undo_delete(&pic, canister_id, 2).map(|todo| todo.id), Ok(2));
+}
+
+#[test]
+fn test_id_reuse_on() {
+    let (pic, canister_id) = setup();
+    let set_reuse_ids = |sender: Principal, enabled: bool| -> Result<(), TodoError> {
+        call_update_as(
+            &pic,
+            canister_id,
+            sender,
+            "set_reuse_ids",
+            encode_one(enabled).unwrap(),
+        )
+    };
+    assert_eq!(
+        set_reuse_ids(Principal::anonymous(), true),
+        Err(TodoError::Unauthorized)
+    );
+    assert_eq!(set_reuse_ids(admin(), true), Ok(()));
+
+    for id in 1..=4 {
+        assert_eq!(add_todo(&pic, canister_id, &format!("Todo {}", id)), Ok(id));
+    }
+    delete_todo(&pic, canister_id, 3).unwrap();
+    delete_todo(&pic, canister_id, 1).unwrap();
+    delete_todo(&pic, canister_id, 4).unwrap();
+
+    // the smallest freed ID comes first, then the counter continues
+    assert_eq!(add_todo(&pic, canister_id, "Reused"), Ok(1));
+    assert_eq!(read_title(&pic, canister_id, 1), Ok("Reused".to_owned()));
+    // a reused ID can't be restored over the new todo
+    assert_eq!(
+        undo_delete(&pic, canister_id, 1),
+        Err(TodoError::NotFound(1))
+    );
+    // an ID restored from the trash is no longer free
+    assert_eq!(undo_delete(&pic, canister_id, 3).map(|todo| todo.id), Ok(3));
+    assert_eq!(add_todo(&pic, canister_id, "Also reused"), Ok(4));
+    assert_eq!(add_todo(&pic, canister_id, "Fresh"), Ok(5));
+
+    // turning reuse off goes back to fresh IDs
+    delete_todo(&pic, canister_id, 2).unwrap();
+    assert_eq!(set_reuse_ids(admin(), false), Ok(()));
+    assert_eq!(add_todo(&pic, canister_id, "Fresh again"), Ok(6));
+}
+
+#[test]
+fn test_next_id() {
+    let (pic, canister_id) = setup();
+    let next_id = || -> u64 { call_query(&pic, canister_id, "next_id", encode_one(()).unwrap()) };
+
+    assert_eq!(next_id(), 1);
+    for _ in 0..3 {
+        let predicted = next_id();
+        assert_eq!(add_todo(&pic, canister_id, "Todo"), Ok(predicted));
+    }
+    // deleted IDs are not predicted while reuse is off
+    delete_todo(&pic, canister_id, 2).unwrap();
+    assert_eq!(next_id(), 4);
+
+    let res: Result<(), TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "set_reuse_ids",
+        encode_one(true).unwrap(),
+    );
+    assert_eq!(res, Ok(()));
+    delete_todo(&pic, canister_id, 1).unwrap();
+    let predicted = next_id();
+    assert_eq!(predicted, 1);
+    assert_eq!(add_todo(&pic, canister_id, "Reused"), Ok(predicted));
+}
+
+#[test]
+fn test_rate_limit() {
+    let (pic, canister_id) = setup_with(InitArgs {
+        max_adds_per_minute: Some(3),
+        ..init_args()
+    });
+    for i in 1..=3 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    assert_eq!(
+        add_todo(&pic, canister_id, "One too many"),
+        Err(TodoError::RateLimited)
+    );
+    // rejected adds are not stored and don't affect other principals
+    assert_eq!(count(&pic, canister_id), 3);
+    let other: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "add",
+        encode_args(("Someone else", "")).unwrap(),
+    );
+    assert!(other.is_ok());
+
+    pic.advance_time(Duration::from_secs(61));
+    pic.tick();
+    assert!(add_todo(&pic, canister_id, "Next minute").is_ok());
+}
+
+#[test]
+fn test_rate_limit_covers_every_way_to_add() {
+    let (pic, canister_id) = setup_with(InitArgs {
+        max_adds_per_minute: Some(3),
+        ..init_args()
+    });
+    let titles = |n: usize| -> Vec<String> { (1..=n).map(|i| format!("Todo {}", i)).collect() };
+
+    // a batch counts each of its todos and is rejected as a whole
+    assert_eq!(
+        add_many(&pic, canister_id, titles(4)),
+        Err(TodoError::RateLimited)
+    );
+    assert_eq!(count(&pic, canister_id), 0);
+    let ids = add_many(&pic, canister_id, titles(3)).unwrap();
+    assert_eq!(
+        add_many(&pic, canister_id, titles(1)),
+        Err(TodoError::RateLimited)
+    );
+    let duplicated: Result<u64, TodoError> =
+        call_update(&pic, canister_id, "duplicate", encode_one(ids[0]).unwrap());
+    assert_eq!(duplicated, Err(TodoError::RateLimited));
+    assert_eq!(
+        add_subtask(&pic, canister_id, ids[0], "Subtask"),
+        Err(TodoError::RateLimited)
+    );
+    assert_eq!(
+        import_json(&pic, canister_id, r#"[{"title": "Imported"}]"#),
+        Err(TodoError::RateLimited)
+    );
+    assert_eq!(count(&pic, canister_id), 3);
+
+    pic.advance_time(Duration::from_secs(61));
+    pic.tick();
+    assert_eq!(
+        add_many(&pic, canister_id, titles(3)).map(|ids| ids.len()),
+        Ok(3)
+    );
+}
+
+#[test]
+fn test_max_todos() {
+    let (pic, canister_id) = setup_with_capacity(3);
+    let first = add_todo(&pic, canister_id, "Todo 1").unwrap();
+    assert_eq!(
+        add_many(
+            &pic,
+            canister_id,
+            vec!["Todo 2".to_owned(), "Todo 3".to_owned()]
+        ),
+        Ok(vec![first + 1, first + 2])
+    );
+    assert_eq!(
+        add_todo(&pic, canister_id, "Todo 4"),
+        Err(TodoError::LimitReached(3))
+    );
+    assert_eq!(
+        add_subtask(&pic, canister_id, first, "Subtask"),
+        Err(TodoError::LimitReached(3))
+    );
+    // a missing todo is reported as such, even when there is no room for a copy
+    let missing: Result<u64, TodoError> = call_update(
+        &pic,
+        canister_id,
+        "duplicate",
+        encode_one(first + 10).unwrap(),
+    );
+    assert_eq!(missing, Err(TodoError::NotFound(first + 10)));
+    // the limit is global, not per principal
+    let other: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "add",
+        encode_args(("Someone else's", "")).unwrap(),
+    );
+    assert_eq!(other, Err(TodoError::LimitReached(3)));
+
+    // deleted todos don't count, but a restore needs room again
+    let deleted = delete_todo(&pic, canister_id, first).unwrap();
+    let replacement = add_todo(&pic, canister_id, "Todo 4").unwrap();
+    assert_eq!(
+        undo_delete(&pic, canister_id, deleted.id),
+        Err(TodoError::LimitReached(3))
+    );
+    delete_todo(&pic, canister_id, replacement).unwrap();
+    assert_eq!(
+        add_many(
+            &pic,
+            canister_id,
+            vec!["Todo 5".to_owned(), "Todo 6".to_owned()]
+        ),
+        Err(TodoError::LimitReached(3))
+    );
+    assert_eq!(count(&pic, canister_id), 2);
+}
+
+#[test]
+fn test_memory_stats() {
+    let (pic, canister_id) = setup();
+    let memory_stats = |pic: &PocketIc| -> MemStats {
+        call_query(pic, canister_id, "memory_stats", encode_one(()).unwrap())
+    };
+    let empty = memory_stats(&pic);
+    assert_eq!(empty.todo_count, 0);
+
+    add_many(
+        &pic,
+        canister_id,
+        (1..=100).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let stats = memory_stats(&pic);
+    assert_eq!(stats.todo_count, 100);
+    assert!(stats.heap_bytes > 0 && stats.heap_bytes % 65536 == 0);
+    assert!(stats.stable_bytes > 0 && stats.stable_bytes % 65536 == 0);
+    assert!(stats.stable_bytes >= empty.stable_bytes);
+    // a few todos are far below 4 GiB of heap
+    assert!(stats.heap_bytes < 4 << 30);
+}
+
+#[test]
+fn test_list() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=25).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let list = |after_id: Option<u64>, limit: u16| -> (Vec<u64>, Option<u64>) {
+        let (todos, cursor): (Vec<Todo>, Option<u64>) = call_query(
+            &pic,
+            canister_id,
+            "list",
+            encode_args((after_id, limit)).unwrap(),
+        );
+        (todos.iter().map(|todo| todo.id).collect(), cursor)
+    };
+
+    let (first, cursor) = list(None, 10);
+    assert_eq!(first, ids[..10].to_vec());
+    assert_eq!(cursor, Some(ids[9]));
+
+    // changes between the calls don't shift the following pages
+    delete_todo(&pic, canister_id, ids[2]).unwrap();
+    delete_todo(&pic, canister_id, ids[12]).unwrap();
+    let added = add_todo(&pic, canister_id, "Late").unwrap();
+    let mut seen = first;
+    let mut cursor = cursor;
+    while let Some(after_id) = cursor {
+        let (page, next) = list(Some(after_id), 10);
+        assert!(page.len() <= 10);
+        seen.extend(page);
+        cursor = next;
+    }
+    let mut expected = ids.clone();
+    expected.retain(|&id| id != ids[12]);
+    expected.push(added);
+    assert_eq!(seen, expected);
+
+    // the cursor is only `None` once no todos follow
+    let (page, cursor) = list(Some(ids[20]), 4);
+    assert_eq!(page, vec![ids[21], ids[22], ids[23], ids[24]]);
+    assert_eq!(cursor, Some(ids[24]));
+    assert_eq!(
+        list(Some(ids[20]), 5),
+        (vec![ids[21], ids[22], ids[23], ids[24], added], None)
+    );
+    assert_eq!(list(Some(added), 10), (vec![], None));
+    assert_eq!(list(Some(u64::MAX), 10), (vec![], None));
+}
+
+#[test]
+fn test_set_priority_many() {
+    let (pic, canister_id) = setup();
+    for i in 1..=4 {
+        add_todo(&pic, canister_id, &format!("Todo {}", i)).unwrap();
+    }
+    let set_priority_many = |ids: Vec<u64>, priority: Priority| -> u64 {
+        call_update(
+            &pic,
+            canister_id,
+            "set_priority_many",
+            encode_args((ids, priority)).unwrap(),
+        )
+    };
+
+    assert_eq!(set_priority_many(vec![1, 3, 99, 4], Priority::High), 3);
+    let priorities: Vec<Priority> = (1..=4)
+        .map(|id| read_todo(&pic, canister_id, id).unwrap().priority)
+        .collect();
+    assert_eq!(
+        priorities,
+        vec![
+            Priority::High,
+            Priority::Medium,
+            Priority::High,
+            Priority::High
+        ]
+    );
+    // unchanged todos don't count
+    assert_eq!(set_priority_many(vec![1, 2, 2], Priority::High), 1);
+    assert_eq!(read_todo(&pic, canister_id, 1).unwrap().version, 1);
+}
+
+#[test]
+fn test_whoami() {
+    let (pic, canister_id) = setup();
+    let whoami = |caller: Principal| -> Principal {
+        call_query_as(&pic, canister_id, caller, "whoami", encode_one(()).unwrap())
+    };
+    let alice = Principal::from_slice(&[3]);
+    let bob = Principal::from_slice(&[4]);
+    assert_eq!(whoami(alice), alice);
+    assert_eq!(whoami(bob), bob);
+    assert_eq!(whoami(Principal::anonymous()), Principal::anonymous());
+}
+
+#[test]
+fn test_subscriber() {
+    let (pic, canister_id) = setup();
+    let subscriber = pic.create_canister();
+    pic.add_cycles(subscriber, 2_000_000_000_000);
+    pic.install_canister(
+        subscriber,
+        load_subscriber_wasm(),
+        encode_args(()).unwrap(),
+        None,
+    );
+    let set_subscriber =
+        |caller: Principal, subscriber: Option<Principal>| -> Result<(), TodoError> {
+            call_update_as(
+                &pic,
+                canister_id,
+                caller,
+                "set_subscriber",
+                encode_one(subscriber).unwrap(),
+            )
+        };
+    let received =
+        || -> Vec<u64> { call_query(&pic, subscriber, "received", encode_one(()).unwrap()) };
+
+    assert_eq!(
+        set_subscriber(Principal::anonymous(), Some(subscriber)),
+        Err(TodoError::Unauthorized)
+    );
+    add_todo(&pic, canister_id, "Before subscribing").unwrap();
+    assert_eq!(set_subscriber(admin(), Some(subscriber)), Ok(()));
+    let first = add_todo(&pic, canister_id, "First").unwrap();
+    let second: Result<u64, TodoError> = call_update(
+        &pic,
+        canister_id,
+        "create",
+        encode_one(CreateTodo {
+            title: "Second".to_owned(),
+            description: String::new(),
+            tags: vec![],
+            priority: Priority::Low,
+            due_at: None,
+        })
+        .unwrap(),
+    );
+    let second = second.unwrap();
+    // batches notify once per todo
+    let batch = add_many(
+        &pic,
+        canister_id,
+        vec!["Third".to_owned(), "Fourth".to_owned()],
+    )
+    .unwrap();
+    let mut expected = vec![first, second];
+    expected.extend(&batch);
+    // a rejected batch creates nothing, so nothing is notified
+    assert!(add_many(&pic, canister_id, vec!["Fine".to_owned(), " ".to_owned()]).is_err());
+    pic.tick();
+    pic.tick();
+    assert_eq!(received(), expected);
+
+    // a subscriber that can't be reached doesn't fail the add
+    let missing = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 99, 1, 1]);
+    assert_eq!(set_subscriber(admin(), Some(missing)), Ok(()));
+    assert!(add_todo(&pic, canister_id, "Unheard").is_ok());
+
+    assert_eq!(set_subscriber(admin(), None), Ok(()));
+    add_todo(&pic, canister_id, "After unsubscribing").unwrap();
+    pic.tick();
+    pic.tick();
+    assert_eq!(received(), expected);
+}
+
+#[test]
+fn test_reminders() {
+    let (pic, canister_id) = setup();
+    let reminders = |pic: &PocketIc| -> Vec<u64> {
+        let events: Vec<Event> = call_query(
+            pic,
+            canister_id,
+            "recent_events",
+            encode_one(100u16).unwrap(),
+        );
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Reminder { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect()
+    };
+    let minute = 60 * 1_000_000_000;
+    let soon = add_todo(&pic, canister_id, "Soon").unwrap();
+    let done = add_todo(&pic, canister_id, "Done").unwrap();
+    let moved = add_todo(&pic, canister_id, "Moved").unwrap();
+    let deleted = add_todo(&pic, canister_id, "Deleted").unwrap();
+    for id in [soon, done, moved, deleted] {
+        assert_eq!(
+            set_due(&pic, canister_id, id, Some(ic_now(&pic) + minute)),
+            Ok(())
+        );
+    }
+    assert_eq!(set_done(&pic, canister_id, "mark_done", done), Ok(()));
+    assert_eq!(
+        set_due(&pic, canister_id, moved, Some(ic_now(&pic) + 10 * minute)),
+        Ok(())
+    );
+    delete_todo(&pic, canister_id, deleted).unwrap();
+
+    pic.advance_time(Duration::from_secs(30));
+    pic.tick();
+    assert!(reminders(&pic).is_empty());
+
+    pic.advance_time(Duration::from_secs(60));
+    pic.tick();
+    pic.tick();
+    assert_eq!(reminders(&pic), vec![soon]);
+
+    // the rescheduled reminder fires at the new due date
+    pic.advance_time(Duration::from_secs(10 * 60));
+    pic.tick();
+    pic.tick();
+    assert_eq!(reminders(&pic), vec![soon, moved]);
+}
+
+#[test]
+fn test_recent_events() {
+    let (pic, canister_id) = setup_with_capacity(10_000);
+    let recent_events = |pic: &PocketIc, limit: u16| -> Vec<Event> {
+        call_query(
+            pic,
+            canister_id,
+            "recent_events",
+            encode_one(limit).unwrap(),
+        )
+    };
+    assert!(recent_events(&pic, 10).is_empty());
+
+    let id = add_todo(&pic, canister_id, "Logged").unwrap();
+    pic.advance_time(Duration::from_secs(1));
+    update_todo(&pic, canister_id, id, "Changed").unwrap();
+    pic.advance_time(Duration::from_secs(1));
+    delete_todo(&pic, canister_id, id).unwrap();
+
+    let events = recent_events(&pic, 10);
+    let (added_at, updated_at, deleted_at) = match events.as_slice() {
+        [Event::Added {
+            id: a,
+            at: added_at,
+        }, Event::Updated {
+            id: u,
+            at: updated_at,
+        }, Event::Deleted {
+            id: d,
+            at: deleted_at,
+        }] if [*a, *u, *d] == [id, id, id] => (*added_at, *updated_at, *deleted_at),
+        other => panic!("Unexpected events: {:?}", other),
+    };
+    assert!(added_at < updated_at && updated_at < deleted_at);
+    assert_eq!(recent_events(&pic, 1), events[2..]);
+
+    // the log keeps the latest 1000 entries
+    add_many(
+        &pic,
+        canister_id,
+        (1..=1000).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    let events = recent_events(&pic, u16::MAX);
+    assert_eq!(events.len(), 1000);
+    assert!(matches!(events[0], Event::Added { id: first, .. } if first == id + 1));
+}
+
+#[test]
+fn test_snapshot_and_restore() {
+    let (pic, canister_id) = setup();
+    let ids = add_many(
+        &pic,
+        canister_id,
+        (1..=5).map(|i| format!("Todo {}", i)).collect(),
+    )
+    .unwrap();
+    assert_eq!(set_done(&pic, canister_id, "mark_done", ids[1]), Ok(()));
+    assert_eq!(
+        edit_tag(&pic, canister_id, "add_tag", ids[2], "work"),
+        Ok(())
+    );
+    delete_todo(&pic, canister_id, ids[3]).unwrap();
+    call_update_as::<Result<u64, TodoError>>(
+        &pic,
+        canister_id,
+        Principal::from_slice(&[3]),
+        "add",
+        encode_args(("Someone else's", "")).unwrap(),
+    )
+    .unwrap();
+
+    let snapshot = |pic: &PocketIc| -> Vec<Todo> {
+        let result: Result<Vec<Todo>, TodoError> = call_query_as(
+            pic,
+            canister_id,
+            admin(),
+            "snapshot",
+            encode_one(()).unwrap(),
+        );
+        result.unwrap()
+    };
+    let before = snapshot(&pic);
+    assert_eq!(before.len(), 5);
+
+    let cleared: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "clear_all",
+        encode_one(()).unwrap(),
+    );
+    assert_eq!(cleared, Ok(5));
+    assert!(snapshot(&pic).is_empty());
+
+    let restored: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "restore",
+        encode_one(before.clone()).unwrap(),
+    );
+    assert_eq!(restored, Ok(5));
+    assert_eq!(snapshot(&pic), before);
+    assert_eq!(
+        read_todo(&pic, canister_id, ids[2]).unwrap().tags,
+        vec!["work".to_string()]
+    );
+    // new IDs continue after the largest restored ID
+    let max_id = before.iter().map(|todo| todo.id).max().unwrap();
+    assert!(add_todo(&pic, canister_id, "After restore").unwrap() > max_id);
+
+    // both endpoints are admin only
+    let denied: Result<Vec<Todo>, TodoError> =
+        call_query(&pic, canister_id, "snapshot", encode_one(()).unwrap());
+    assert_eq!(denied, Err(TodoError::Unauthorized));
+    let denied: Result<u64, TodoError> = call_update(
+        &pic,
+        canister_id,
+        "restore",
+        encode_one(Vec::<Todo>::new()).unwrap(),
+    );
+    assert_eq!(denied, Err(TodoError::Unauthorized));
+    assert_eq!(count(&pic, canister_id), 5);
+}
+
+#[test]
+fn test_restore_validation() {
+    let (pic, canister_id) = setup();
+    let parent = add_todo(&pic, canister_id, "Parent").unwrap();
+    let child = add_subtask(&pic, canister_id, parent, "Child").unwrap();
+    let snapshot: Result<Vec<Todo>, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        admin(),
+        "snapshot",
+        encode_one(()).unwrap(),
+    );
+    let snapshot = snapshot.unwrap();
+    let restore = |todos: Vec<Todo>| -> Result<u64, TodoError> {
+        call_update_as(
+            &pic,
+            canister_id,
+            admin(),
+            "restore",
+            encode_one(todos).unwrap(),
+        )
+    };
+    let broken = |edit: &dyn Fn(&mut Vec<Todo>)| {
+        let mut todos = snapshot.clone();
+        edit(&mut todos);
+        todos
+    };
+
+    let invalid = [
+        broken(&|todos| todos[0].title = " ".to_owned()),
+        broken(&|todos| todos[0].color = Some("red".to_owned())),
+        broken(&|todos| todos[0].tags = vec!["Work".to_owned()]),
+        broken(&|todos| todos[0].tags = vec!["work".to_owned(), "work".to_owned()]),
+        broken(&|todos| todos[1].parent_id = Some(child + 10)),
+        broken(&|todos| todos[1].parent_id = Some(child)),
+        broken(&|todos| todos[0].parent_id = Some(child)),
+        broken(&|todos| todos[0].owner = Principal::from_slice(&[3])),
+    ];
+    for todos in invalid {
+        assert!(matches!(restore(todos), Err(TodoError::InvalidInput(_))));
+    }
+    // nothing was replaced by the rejected restores
+    assert_eq!(count(&pic, canister_id), 2);
+    assert_eq!(
+        read_todo(&pic, canister_id, child).unwrap().parent_id,
+        Some(parent)
+    );
+    assert_eq!(restore(snapshot), Ok(2));
+}
+
+#[test]
+fn test_undo_delete_after_restore() {
+    let (pic, canister_id) = setup();
+    let id = add_todo(&pic, canister_id, "Original").unwrap();
+    let snapshot: Result<Vec<Todo>, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        admin(),
+        "snapshot",
+        encode_one(()).unwrap(),
+    );
+    let snapshot = snapshot.unwrap();
+    assert!(update_todo(&pic, canister_id, id, "Changed").is_ok());
+    delete_todo(&pic, canister_id, id).unwrap();
+
+    let restored: Result<u64, TodoError> = call_update_as(
+        &pic,
+        canister_id,
+        admin(),
+        "restore",
+        encode_one(snapshot).unwrap(),
+    );
+    assert_eq!(restored, Ok(1));
+    // the deleted todo left with the trash, the restored one stays untouched
+    assert_eq!(
+        undo_delete(&pic, canister_id, id),
+        Err(TodoError::NotFound(id))
+    );
+    assert_eq!(read_title(&pic, canister_id, id), Ok("Original".to_owned()));
+    assert_eq!(count(&pic, canister_id), 1);
+}
+
+#[test]
+fn test_read_certified() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "Certified").unwrap();
+    let read_certified = |id: u64| -> Result<(Todo, Vec<u8>), TodoError> {
+        call_query(&pic, canister_id, "read_certified", encode_one(id).unwrap())
+    };
+
+    let (todo, certificate) = read_certified(1).unwrap();
+    assert_eq!(todo.title, "Certified");
+    // certificates are only handed out in query calls
+    assert!(!certificate.is_empty());
+    let res: Result<(Todo, Vec<u8>), TodoError> = call_update(
+        &pic,
+        canister_id,
+        "read_certified",
+        encode_one(1u64).unwrap(),
+    );
+    assert!(res.unwrap().1.is_empty());
+    assert_eq!(read_certified(2), Err(TodoError::NotFound(2)));
+
+    // the certificate changes with the store and survives upgrades
+    update_todo(&pic, canister_id, 1, "Changed").unwrap();
+    let (_, changed) = read_certified(1).unwrap();
+    assert_ne!(changed, certificate);
+    pic.upgrade_canister(canister_id, load_todos_wasm(), vec![], None)
+        .expect("Failed to upgrade todo canister");
+    assert!(!read_certified(1).unwrap().1.is_empty());
+}
+
+#[test]
+fn test_read_composite() {
+    let (pic, canister_id) = setup();
+    add_todo(&pic, canister_id, "First").unwrap();
+    let other = Principal::from_slice(&[7]);
+
+    let res: Result<Todo, TodoError> = call_query(
+        &pic,
+        canister_id,
+        "read_composite",
+        encode_one(1u64).unwrap(),
+    );
+    assert_eq!(res, read_todo(&pic, canister_id, 1));
+    assert_eq!(res.map(|todo| todo.title), Ok("First".to_owned()));
+
+    let res: Result<Todo, TodoError> = call_query(
+        &pic,
+        canister_id,
+        "read_composite",
+        encode_one(2u64).unwrap(),
+    );
+    assert_eq!(res, Err(TodoError::NotFound(2)));
+    // composite reads are scoped to the caller like `read`
+    let res: Result<Todo, TodoError> = call_query_as(
+        &pic,
+        canister_id,
+        other,
+        "read_composite",
+        encode_one(1u64).unwrap(),
+    );
+    assert_eq!(res, Err(TodoError::NotFound(1)));
+}
+
+/// Creates a fresh PocketIC instance with the todo canister installed.
+fn setup() -> (PocketIc, CanisterId) {
+    setup_with(init_args())
+}
+
+/// The init arguments `setup` installs the canister with.
+fn init_args() -> InitArgs {
+    InitArgs {
+        default_page_size: 10,
+        admin: admin(),
+        done_ttl_nanos: None,
+        max_todos: None,
+        max_adds_per_minute: None,
+    }
+}
+
+/// Like `setup`, but with room for `max_todos` todos instead of the default 10000 and
+/// without a practical rate limit, so tests can add todos in bulk.
+fn setup_with_capacity(max_todos: u64) -> (PocketIc, CanisterId) {
+    setup_with(InitArgs {
+        max_todos: Some(max_todos),
+        max_adds_per_minute: Some(u32::MAX),
+        ..init_args()
+    })
+}
+
+/// Like `setup`, but installs the canister with custom init arguments.
+fn setup_with(args: InitArgs) -> (PocketIc, CanisterId) {
+    let pic = PocketIc::new();
+    let canister_id = pic.create_canister();
+    pic.add_cycles(canister_id, 2_000_000_000_000);
+    pic.install_canister(
+        canister_id,
+        load_todos_wasm(),
+        encode_one(args).unwrap(),
+        None,
+    );
+    (pic, canister_id)
+}
+
+/// The admin principal `setup` installs the canister with.
+fn admin() -> Principal {
+    Principal::from_slice(&[42])
+}
+
+/// Decodes a single candid value from a successful reply.
+fn decode_reply<T: CandidType + for<'a> Deserialize<'a>>(res: WasmResult) -> T {
+    match res {
+        WasmResult::Reply(bytes) => decode_one(&bytes).expect("Failed to decode reply"),
+        WasmResult::Reject(msg) => panic!("Call rejected: {}", msg),
+    }
+}
+
+/// Makes an update call as `sender` and decodes the reply.
+fn call_update_as<T: CandidType + for<'a> Deserialize<'a>>(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    sender: Principal,
+    method: &str,
+    arg: Vec<u8>,
+) -> T {
+    let res = pic
+        .update_call(canister_id, sender, method, arg)
+        .expect("Failed to call todo canister");
+    decode_reply(res)
+}
+
+/// Makes a query call as `sender` and decodes the reply.
+fn call_query_as<T: CandidType + for<'a> Deserialize<'a>>(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    sender: Principal,
+    method: &str,
+    arg: Vec<u8>,
+) -> T {
+    let res = pic
+        .query_call(canister_id, sender, method, arg)
+        .expect("Failed to call todo canister");
+    decode_reply(res)
+}
+
+/// Makes an update call as the anonymous principal and decodes the reply.
+fn call_update<T: CandidType + for<'a> Deserialize<'a>>(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    method: &str,
+    arg: Vec<u8>,
+) -> T {
+    call_update_as(pic, canister_id, Principal::anonymous(), method, arg)
+}
+
+/// Makes a query call as the anonymous principal and decodes the reply.
+fn call_query<T: CandidType + for<'a> Deserialize<'a>>(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    method: &str,
+    arg: Vec<u8>,
+) -> T {
+    call_query_as(pic, canister_id, Principal::anonymous(), method, arg)
+}
+
+fn add_todo(pic: &PocketIc, canister_id: CanisterId, title: &str) -> Result<u64, TodoError> {
+    add_described(pic, canister_id, title, "")
+}
+
+/// Like `add_todo`, but adds the todo as `sender`.
+fn add_as(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    sender: Principal,
+    title: &str,
+) -> Result<u64, TodoError> {
+    call_update_as(
+        pic,
+        canister_id,
+        sender,
+        "add",
+        encode_args((title, "")).unwrap(),
+    )
+}
+
+fn add_described(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    title: &str,
+    description: &str,
+) -> Result<u64, TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "add",
+        encode_args((title, description)).unwrap(),
+    )
+}
+
+fn add_many(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    contents: Vec<String>,
+) -> Result<Vec<u64>, TodoError> {
+    call_update(pic, canister_id, "add_many", encode_one(contents).unwrap())
+}
+
+fn search(pic: &PocketIc, canister_id: CanisterId, term: &str) -> Vec<Todo> {
+    call_query(pic, canister_id, "search", encode_one(term).unwrap())
+}
+
+fn search_prefix(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    prefix: &str,
+    page: u16,
+) -> Result<(Vec<Todo>, Option<u16>), TodoError> {
+    call_query(
+        pic,
+        canister_id,
+        "search_prefix",
+        encode_args((prefix, page)).unwrap(),
+    )
+}
+
+fn exists(pic: &PocketIc, canister_id: CanisterId, id: u64) -> bool {
+    call_query(pic, canister_id, "exists", encode_one(id).unwrap())
+}
+
+fn undo_delete(pic: &PocketIc, canister_id: CanisterId, id: u64) -> Result<Todo, TodoError> {
+    call_update(pic, canister_id, "undo_delete", encode_one(id).unwrap())
+}
+
+fn set_priority(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    id: u64,
+    priority: Priority,
+) -> Result<(), TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "set_priority",
+        encode_args((id, priority)).unwrap(),
+    )
+}
+
+fn set_due(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    id: u64,
+    due_at: Option<u64>,
+) -> Result<(), TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "set_due",
+        encode_args((id, due_at)).unwrap(),
+    )
+}
+
+fn overdue_ids(pic: &PocketIc, canister_id: CanisterId) -> Vec<u64> {
+    let todos: Vec<Todo> = call_query(pic, canister_id, "overdue", encode_args(()).unwrap());
+    todos.into_iter().map(|todo| todo.id).collect()
+}
+
+fn edit_tag(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    method: &str,
+    id: u64,
+    tag: &str,
+) -> Result<(), TodoError> {
+    call_update(pic, canister_id, method, encode_args((id, tag)).unwrap())
+}
+
+fn by_tag(pic: &PocketIc, canister_id: CanisterId, tag: &str) -> Vec<u64> {
+    let todos: Vec<Todo> = call_query(pic, canister_id, "by_tag", encode_one(tag).unwrap());
+    todos.into_iter().map(|todo| todo.id).collect()
+}
+
+fn reorder(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    id: u64,
+    new_position: u32,
+) -> Result<(), TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "reorder",
+        encode_args((id, new_position)).unwrap(),
+    )
+}
+
+fn ordered_ids(pic: &PocketIc, canister_id: CanisterId) -> Vec<u64> {
+    let page: Result<Page, TodoError> = call_query(
+        pic,
+        canister_id,
+        "read_all_ordered",
+        encode_one(1u16).unwrap(),
+    );
+    page.unwrap().items.iter().map(|todo| todo.id).collect()
+}
+
+fn import_json(pic: &PocketIc, canister_id: CanisterId, json: &str) -> Result<u64, TodoError> {
+    call_update(pic, canister_id, "import_json", encode_one(json).unwrap())
+}
+
+fn http_get(pic: &PocketIc, canister_id: CanisterId, url: &str) -> HttpResponse {
+    let request = HttpRequest {
+        method: "GET".to_owned(),
+        url: url.to_owned(),
+        headers: vec![],
+        body: vec![],
+    };
+    call_query(
+        pic,
+        canister_id,
+        "http_request",
+        encode_one(request).unwrap(),
+    )
+}
+
+fn add_subtask(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    parent_id: u64,
+    title: &str,
+) -> Result<u64, TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "add_subtask",
+        encode_args((parent_id, title)).unwrap(),
+    )
+}
+
+fn children_ids(pic: &PocketIc, canister_id: CanisterId, parent_id: u64) -> Vec<u64> {
+    let todos: Vec<Todo> = call_query(pic, canister_id, "children", encode_one(parent_id).unwrap());
+    todos.into_iter().map(|todo| todo.id).collect()
+}
+
+fn read_todo(pic: &PocketIc, canister_id: CanisterId, id: u64) -> Result<Todo, TodoError> {
+    call_query(pic, canister_id, "read", encode_one(id).unwrap())
+}
+
+/// Like `read_todo`, but reads the todo as `sender`.
+fn read_as(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    sender: Principal,
+    id: u64,
+) -> Result<Todo, TodoError> {
+    call_query_as(pic, canister_id, sender, "read", encode_one(id).unwrap())
+}
+
+fn read_title(pic: &PocketIc, canister_id: CanisterId, id: u64) -> Result<String, TodoError> {
+    read_todo(pic, canister_id, id).map(|todo| todo.title)
+}
+
+fn read_description(pic: &PocketIc, canister_id: CanisterId, id: u64) -> Result<String, TodoError> {
+    read_todo(pic, canister_id, id).map(|todo| todo.description)
+}
+
+fn read_all(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    page: u16,
+    page_size: u16,
+) -> Result<(Vec<Todo>, Option<u16>), TodoError> {
+    read_page(pic, canister_id, page, page_size).map(|page| (page.items, page.next_page))
+}
+
+fn read_page(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    page: u16,
+    page_size: u16,
+) -> Result<Page, TodoError> {
+    call_query(
+        pic,
+        canister_id,
+        "read_all",
+        encode_args((page, page_size)).unwrap(),
+    )
+}
+
+/// Updates the title based on the current version, so no conflict can occur.
+fn update_todo(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    id: u64,
+    title: &str,
+) -> Result<String, TodoError> {
+    let version = read_todo(pic, canister_id, id).map_or(0, |todo| todo.version);
+    update_todo_at(pic, canister_id, id, title, version)
+}
+
+fn update_todo_at(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    id: u64,
+    title: &str,
+    expected_version: u64,
+) -> Result<String, TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "update",
+        encode_args((id, title, expected_version)).unwrap(),
+    )
+}
+
+fn update_description(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    id: u64,
+    description: &str,
+) -> Result<String, TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        "update_description",
+        encode_args((id, description)).unwrap(),
+    )
+}
+
+fn delete_todo(pic: &PocketIc, canister_id: CanisterId, id: u64) -> Result<Todo, TodoError> {
+    call_update(pic, canister_id, "delete", encode_one(id).unwrap())
+}
+
+/// Calls `pin` or `unpin`.
+fn set_pinned(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    pinned: bool,
+    id: u64,
+) -> Result<(), TodoError> {
+    let method = if pinned { "pin" } else { "unpin" };
+    call_update(pic, canister_id, method, encode_one(id).unwrap())
+}
+
+/// Calls `star` or `unstar`.
+fn set_starred(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    starred: bool,
+    id: u64,
+) -> Result<(), TodoError> {
+    let method = if starred { "star" } else { "unstar" };
+    call_update(pic, canister_id, method, encode_one(id).unwrap())
+}
+
+fn set_done(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    method: &str,
+    id: u64,
+) -> Result<(), TodoError> {
+    // `mark_done` replies with the ID of the next instance of a recurring todo, the other
+    // methods with nothing, which decodes as `None`.
+    let res: Result<Option<u64>, TodoError> =
+        call_update(pic, canister_id, method, encode_one(id).unwrap());
+    res.map(|_| ())
+}
+
+/// The current IC time of `pic` in nanoseconds since the UNIX epoch.
+fn ic_now(pic: &PocketIc) -> u64 {
+    pic.get_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+fn count(pic: &PocketIc, canister_id: CanisterId) -> u64 {
+    call_query(pic, canister_id, "count", encode_one(()).unwrap())
+}
+
+fn add_new_todo(pic: &PocketIc, canister_id: CanisterId, method: &str) -> Result<u64, TodoError> {
+    call_update(
+        pic,
+        canister_id,
+        method,
+        encode_args(("Content First Todo", "")).unwrap(),
+    )
+}
+
+fn get_todo(
+    pic: &PocketIc,
+    canister_id: CanisterId,
+    method: &str,
+    todoid: u64,
+) -> Result<Todo, TodoError> {
+    call_query(pic, canister_id, method, encode_one(todoid).unwrap())
+}
+
+/// Default location of the canister wasm, relative to this crate, as produced by
+/// `cargo build --target wasm32-unknown-unknown --release -p todo_rust_backend`.
+const DEFAULT_WASM_PATH: &str = "../target/wasm32-unknown-unknown/release/todo_rust_backend.wasm";
+
+/// Default location of the subscriber wasm used by `test_subscriber`, as produced by
+/// `cargo build --target wasm32-unknown-unknown --release --manifest-path mock_subscriber/Cargo.toml`.
+const DEFAULT_SUBSCRIBER_WASM_PATH: &str =
+    "../mock_subscriber/target/wasm32-unknown-unknown/release/mock_subscriber.wasm";
+
+fn load_todos_wasm() -> Vec<u8> {
+    // load the todo's wasm by opening it as Vec<u8>; `CANISTER_WASM_PATH` overrides
+    // the default build output path.
+    load_wasm("CANISTER_WASM_PATH", DEFAULT_WASM_PATH)
+}
+
+fn load_subscriber_wasm() -> Vec<u8> {
+    load_wasm("SUBSCRIBER_WASM_PATH", DEFAULT_SUBSCRIBER_WASM_PATH)
+}
+
+/// Reads the wasm at the path in the environment variable `var`, or at `default_path`
+/// relative to this crate if it is not set.
+fn load_wasm(var: &str, default_path: &str) -> Vec<u8> {
+    let wasm_path = std::env::var(var).map_or_else(
+        |_| Path::new(env!("CARGO_MANIFEST_DIR")).join(default_path),
+        PathBuf::from,
+    );
+    let mut file = File::open(&wasm_path).unwrap_or_else(|err| {
+        panic!(
+            "Failed to open canister wasm at {}: {} (build it first or set {})",
+            wasm_path.display(),
+            err,
+            var
+        )
+    });
+    let mut wasm = Vec::new();
+    file.read_to_end(&mut wasm)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {}", wasm_path.display(), err));
+    wasm
 }